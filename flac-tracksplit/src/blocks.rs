@@ -0,0 +1,58 @@
+use metaflac::Block;
+use std::io::{self, ErrorKind, Read};
+
+/// The type of STREAMINFO metadata blocks.
+pub(crate) const STREAMINFO_BLOCK_TYPE: u8 = 0;
+
+/// The type of PADDING metadata blocks.
+pub(crate) const PADDING_BLOCK_TYPE: u8 = 1;
+
+/// The type of VORBIS_COMMENT metadata blocks.
+pub(crate) const VORBIS_COMMENT_BLOCK_TYPE: u8 = 4;
+
+/// The type of CUESHEET metadata blocks.
+pub(crate) const CUESHEET_BLOCK_TYPE: u8 = 5;
+
+/// The type of PICTURE metadata blocks.
+pub(crate) const PICTURE_BLOCK_TYPE: u8 = 6;
+
+/// A metadata block's type and contents, as they are stored in a file.
+pub(crate) type RawBlock = (u8, Vec<u8>);
+
+/// Read the FLAC stream marker and all metadata blocks (including
+/// padding) from `from`, leaving it at the first audio frame.
+///
+/// Unlike [metaflac::Tag], this doesn't parse the blocks, so it keeps
+/// them exactly as they are, and doesn't fail on blocks that metaflac
+/// can't parse (e.g. pictures of types it doesn't know).
+pub(crate) fn read_raw_blocks(from: &mut impl Read) -> io::Result<Vec<RawBlock>> {
+    let mut magic = [0u8; 4];
+    from.read_exact(&mut magic)?;
+    if &magic != b"fLaC" {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "no FLAC stream marker",
+        ));
+    }
+    let mut blocks = vec![];
+    loop {
+        let mut header = [0u8; 4];
+        from.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]);
+        let mut contents = vec![0u8; length as usize];
+        from.read_exact(&mut contents)?;
+        blocks.push((header[0] & 0x7f, contents));
+        if header[0] & 0x80 != 0 {
+            return Ok(blocks);
+        }
+    }
+}
+
+/// A metadata block's type and contents, as they are written to a
+/// file.
+pub(crate) fn raw_block(block: &Block) -> Option<RawBlock> {
+    let mut bytes = vec![];
+    block.write_to(false, &mut bytes).ok()?;
+    let contents = bytes.split_off(4);
+    Some((bytes[0] & 0x7f, contents))
+}
@@ -0,0 +1,468 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, hash_map::Entry},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::warn;
+
+use crate::{Error, Result, SplitPlan};
+
+/// How to handle two or more planned tracks (or album files, such as
+/// playlists) that would be written to the same output path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionStrategy {
+    /// Refuse to split any of the files.
+    #[default]
+    Error,
+
+    /// Keep the first track's path, and add a numeric suffix like
+    /// ` (2)` to the file names of the others. Colliding album files
+    /// get the suffix for all of a disc image's album files.
+    Suffix,
+
+    /// Tell the colliding discs apart by including the disc number in
+    /// all their track and album file names, and if that is not
+    /// enough, the release's catalog number in their album directory
+    /// names.
+    Disambiguate,
+}
+
+impl FromStr for CollisionStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(CollisionStrategy::Error),
+            "suffix" => Ok(CollisionStrategy::Suffix),
+            "disambiguate" => Ok(CollisionStrategy::Disambiguate),
+            _ => Err(Error::UnknownOption {
+                option: "collision strategy",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for CollisionStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CollisionStrategy::Error => "error",
+            CollisionStrategy::Suffix => "suffix",
+            CollisionStrategy::Disambiguate => "disambiguate",
+        })
+    }
+}
+
+/// Identifies a track in a list of [SplitPlan]s, by the plan's index and
+/// the track's index in that plan.
+type TrackRef = (usize, usize);
+
+/// Paths are compared case-insensitively, since they might well end up
+/// on a case-insensitive filesystem.
+fn collision_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+/// Returns every track whose output path is already taken by an
+/// earlier track, paired with that earlier track.
+fn find_collisions(plans: &[SplitPlan]) -> Vec<(TrackRef, TrackRef)> {
+    let mut seen: HashMap<String, TrackRef> = HashMap::new();
+    let mut collisions = vec![];
+    for (p, plan) in plans.iter().enumerate() {
+        for (t, planned) in plan.tracks.iter().enumerate() {
+            match seen.entry(collision_key(&planned.output_path)) {
+                Entry::Vacant(entry) => {
+                    entry.insert((p, t));
+                }
+                Entry::Occupied(entry) => collisions.push(((p, t), *entry.get())),
+            }
+        }
+    }
+    collisions
+}
+
+/// Returns every album file (such as a playlist) whose path is
+/// already taken by an earlier plan's album file, as the path and the
+/// plans' indices. Pictures that are the same in both plans don't
+/// collide, e.g. the cover shared by an album's discs.
+fn find_album_collisions(plans: &[SplitPlan]) -> Vec<(PathBuf, usize, usize)> {
+    let mut seen: HashMap<String, (usize, Option<&[u8]>)> = HashMap::new();
+    let mut collisions = vec![];
+    for (p, plan) in plans.iter().enumerate() {
+        for (path, contents) in plan.album_files() {
+            match seen.entry(collision_key(&path)) {
+                Entry::Vacant(entry) => {
+                    entry.insert((p, contents));
+                }
+                Entry::Occupied(entry) => match *entry.get() {
+                    (_, Some(earlier)) if contents == Some(earlier) => {}
+                    (earlier_p, _) => collisions.push((path, p, earlier_p)),
+                },
+            }
+        }
+    }
+    collisions
+}
+
+/// Returns the indices of all `plans` that have a track or album file
+/// whose output path collides with another plan's.
+pub fn colliding_plans(plans: &[SplitPlan]) -> BTreeSet<usize> {
+    find_collisions(plans)
+        .iter()
+        .flat_map(|((p, _), (earlier_p, _))| [*p, *earlier_p])
+        .chain(
+            find_album_collisions(plans)
+                .iter()
+                .flat_map(|(_, p, earlier_p)| [*p, *earlier_p]),
+        )
+        .collect()
+}
+
+fn describe(plans: &[SplitPlan], (p, t): TrackRef) -> String {
+    format!(
+        "{:?} track {}",
+        plans[p].input_path(),
+        plans[p].tracks[t].track.number
+    )
+}
+
+/// Detect tracks (and album files, such as playlists) across all
+/// `plans` that would be written to the same output path, and resolve
+/// those collisions according to `strategy`.
+///
+/// This only adjusts the planned output paths, so it should be called
+/// before any of the plans get executed.
+pub fn resolve_collisions(plans: &mut [SplitPlan], strategy: CollisionStrategy) -> Result<()> {
+    let collisions = find_collisions(plans);
+    let album_collisions = find_album_collisions(plans);
+    if collisions.is_empty() && album_collisions.is_empty() {
+        return Ok(());
+    }
+    match strategy {
+        CollisionStrategy::Error => {
+            let mut paths = vec![];
+            let mut descriptions = vec![];
+            for &(track, earlier) in &collisions {
+                let path = &plans[track.0].tracks[track.1].output_path;
+                descriptions.push(format!(
+                    "{:?} (from {} and {})",
+                    path,
+                    describe(plans, earlier),
+                    describe(plans, track)
+                ));
+                paths.push(path.clone());
+            }
+            for (path, p, earlier_p) in album_collisions {
+                descriptions.push(format!(
+                    "{:?} (from {:?} and {:?})",
+                    path,
+                    plans[earlier_p].input_path(),
+                    plans[p].input_path()
+                ));
+                paths.push(path);
+            }
+            Err(Error::OutputCollision {
+                paths,
+                description: descriptions.join(", "),
+            })
+        }
+        CollisionStrategy::Suffix => {
+            let mut taken: HashSet<String> = plans
+                .iter()
+                .flat_map(|plan| plan.tracks.iter())
+                .map(|planned| collision_key(&planned.output_path))
+                .collect();
+            for ((p, t), earlier) in collisions {
+                let original = plans[p].tracks[t].output_path.clone();
+                for n in 2.. {
+                    let (path, mut warnings) =
+                        plans[p].path_for(&plans[p].tracks[t].track, |parts| {
+                            parts.suffix = Some(format!(" ({})", n));
+                        })?;
+                    if taken.insert(collision_key(&path)) {
+                        let colliding_with = describe(plans, earlier);
+                        warn!(
+                            original = ?original,
+                            renamed = ?path,
+                            colliding_with,
+                            "Renamed colliding output path."
+                        );
+                        warnings.push(format!(
+                            "renamed from {:?}, which {} is written to as well",
+                            original, colliding_with
+                        ));
+                        plans[p].set_output_path(t, (path, warnings));
+                        break;
+                    }
+                }
+            }
+            let mut taken: HashSet<String> = HashSet::new();
+            let mut renamed = BTreeSet::new();
+            for (p, plan) in plans.iter().enumerate() {
+                if !album_collisions
+                    .iter()
+                    .any(|&(_, colliding, _)| colliding == p)
+                {
+                    taken.extend(
+                        plan.album_files()
+                            .iter()
+                            .map(|(path, _)| collision_key(path)),
+                    );
+                }
+            }
+            for (_, p, earlier_p) in album_collisions {
+                if !renamed.insert(p) {
+                    continue;
+                }
+                for n in 2.. {
+                    plans[p].album_file_suffix = Some(format!(" ({})", n));
+                    let keys: Vec<String> = plans[p]
+                        .album_files()
+                        .iter()
+                        .map(|(path, _)| collision_key(path))
+                        .collect();
+                    if keys.iter().all(|key| !taken.contains(key)) {
+                        taken.extend(keys);
+                        let colliding_with = plans[earlier_p].input_path().to_path_buf();
+                        warn!(
+                            input_path = ?plans[p].input_path(),
+                            ?colliding_with,
+                            "Renamed colliding album files."
+                        );
+                        plans[p].warnings.push(format!(
+                            "album files are named with the suffix \" ({})\", as {:?} writes them as well",
+                            n, colliding_with
+                        ));
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        }
+        CollisionStrategy::Disambiguate => {
+            let involved = colliding_plans(plans);
+            // First try telling discs apart by their disc number, and
+            // if there are still collisions, add their catalog number
+            // too:
+            for with_catalog in [false, true] {
+                for &p in &involved {
+                    plans[p].replan_paths(|track, parts| {
+                        if parts.disc.is_some() {
+                            parts.show_disc = true;
+                        }
+                        if with_catalog && let Some(catalog) = track.catalog_number() {
+                            parts.album_suffix = Some(format!(" [{}]", catalog));
+                        }
+                    })?;
+                    // The album files, cover included, get the disc
+                    // number as well:
+                    plans[p].album_file_suffix = plans[p]
+                        .tracks
+                        .first()
+                        .and_then(|first| first.track.path_parts().disc)
+                        .map(|disc| format!(" (Disc {})", disc));
+                    warn!(
+                        input_path = ?plans[p].input_path(),
+                        with_catalog,
+                        "Disambiguating output paths of colliding disc image."
+                    );
+                }
+                if find_collisions(plans).is_empty() && find_album_collisions(plans).is_empty() {
+                    let added = if with_catalog {
+                        "disc and catalog number"
+                    } else {
+                        "disc number"
+                    };
+                    for &p in &involved {
+                        plans[p].warnings.push(format!(
+                            "output paths include the {}, as they collided with another disc image's",
+                            added
+                        ));
+                    }
+                    return Ok(());
+                }
+            }
+            resolve_collisions(plans, CollisionStrategy::Error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{PlaylistFormat, SplitOptions, Track, report::CueSummary};
+    use metaflac::block::StreamInfo;
+    use symphonia_core::{
+        formats::Cue,
+        meta::{Tag, Value},
+    };
+
+    fn disc(input: &str, tags: &[(&str, &str)]) -> SplitPlan {
+        disc_with(input, tags, &SplitOptions::new("/out"))
+    }
+
+    fn disc_with(input: &str, tags: &[(&str, &str)], options: &SplitOptions) -> SplitPlan {
+        let tags: Vec<Tag> = tags
+            .iter()
+            .map(|(k, v)| Tag::new(None, k, Value::from(*v)))
+            .collect();
+        let tracks = (1..=2)
+            .map(|index| {
+                let cue = Cue {
+                    index,
+                    start_ts: u64::from(index) * 1000,
+                    tags: vec![],
+                    points: vec![],
+                };
+                Track::from_tags(
+                    &StreamInfo::new(),
+                    &cue,
+                    10000,
+                    &tags,
+                    &options.track_tag_conventions,
+                    &[],
+                    None,
+                    None,
+                )
+                .unwrap()
+            })
+            .collect();
+        let cue = CueSummary::new(&StreamInfo::new(), &[]);
+        SplitPlan::new(Path::new(input), options, cue, tracks).unwrap()
+    }
+
+    fn paths(plans: &[SplitPlan]) -> Vec<String> {
+        plans
+            .iter()
+            .flat_map(|plan| plan.tracks())
+            .map(|planned| planned.output_path.to_string_lossy().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn collision_strategies() {
+        let album = [("ALBUM", "Album"), ("ARTIST", "Artist")];
+        let discs = || {
+            vec![
+                disc("a.flac", &[album[0], album[1], ("DISCNUMBER", "1")]),
+                disc("b.flac", &[album[0], album[1], ("DISCNUMBER", "2")]),
+            ]
+        };
+
+        assert!(resolve_collisions(&mut discs(), CollisionStrategy::Error).is_err());
+
+        let mut plans = discs();
+        resolve_collisions(&mut plans, CollisionStrategy::Suffix).unwrap();
+        assert_eq!(
+            paths(&plans),
+            vec![
+                "/out/Artist/Album/01.flac",
+                "/out/Artist/Album/02.flac",
+                "/out/Artist/Album/01 (2).flac",
+                "/out/Artist/Album/02 (2).flac",
+            ]
+        );
+
+        let mut plans = discs();
+        resolve_collisions(&mut plans, CollisionStrategy::Disambiguate).unwrap();
+        assert_eq!(
+            paths(&plans),
+            vec![
+                "/out/Artist/Album/01-01.flac",
+                "/out/Artist/Album/01-02.flac",
+                "/out/Artist/Album/02-01.flac",
+                "/out/Artist/Album/02-02.flac",
+            ]
+        );
+
+        let mut plans = vec![
+            disc("a.flac", &[album[0], album[1], ("CATALOGNUMBER", "A1")]),
+            disc("b.flac", &[album[0], album[1], ("CATALOGNUMBER", "B2")]),
+        ];
+        resolve_collisions(&mut plans, CollisionStrategy::Disambiguate).unwrap();
+        assert_eq!(
+            paths(&plans),
+            vec![
+                "/out/Artist/Album [A1]/01.flac",
+                "/out/Artist/Album [A1]/02.flac",
+                "/out/Artist/Album [B2]/01.flac",
+                "/out/Artist/Album [B2]/02.flac",
+            ]
+        );
+    }
+
+    #[test]
+    fn album_file_collisions() {
+        let options = SplitOptions::new("/out")
+            .playlists([PlaylistFormat::M3u8])
+            .cue_sheet(true);
+        let album_files = |plans: &[SplitPlan]| -> Vec<String> {
+            plans
+                .iter()
+                .flat_map(|plan| plan.album_files())
+                .map(|(path, _)| path.to_string_lossy().into_owned())
+                .collect()
+        };
+        // Tracks that don't collide, in the same album directory:
+        let discs = || {
+            let album = [("ALBUM", "Album"), ("ARTIST", "Artist")];
+            vec![
+                disc_with("a.flac", &[album[0], album[1], ("TITLE", "A")], &options),
+                disc_with("b.flac", &[album[0], album[1], ("TITLE", "B")], &options),
+            ]
+        };
+        let mut plans = discs();
+        assert_eq!(colliding_plans(&plans), BTreeSet::from([0, 1]));
+        let Err(Error::OutputCollision { paths, .. }) =
+            resolve_collisions(&mut plans, CollisionStrategy::Error)
+        else {
+            panic!("album files collide");
+        };
+        assert_eq!(
+            paths,
+            [
+                Path::new("/out/Artist/Album/Album.m3u8"),
+                Path::new("/out/Artist/Album/Album.cue")
+            ]
+        );
+
+        let mut plans = discs();
+        resolve_collisions(&mut plans, CollisionStrategy::Suffix).unwrap();
+        assert_eq!(
+            album_files(&plans),
+            vec![
+                "/out/Artist/Album/Album.m3u8",
+                "/out/Artist/Album/Album.cue",
+                "/out/Artist/Album/Album (2).m3u8",
+                "/out/Artist/Album/Album (2).cue",
+            ]
+        );
+
+        // Discs numbered without a total share their track file names
+        // as well as their album files:
+        let album = [("ALBUM", "Album"), ("ARTIST", "Artist")];
+        let mut plans = vec![
+            disc_with(
+                "a.flac",
+                &[album[0], album[1], ("DISCNUMBER", "1")],
+                &options,
+            ),
+            disc_with(
+                "b.flac",
+                &[album[0], album[1], ("DISCNUMBER", "2")],
+                &options,
+            ),
+        ];
+        resolve_collisions(&mut plans, CollisionStrategy::Disambiguate).unwrap();
+        assert_eq!(
+            album_files(&plans),
+            vec![
+                "/out/Artist/Album/Album (Disc 01).m3u8",
+                "/out/Artist/Album/Album (Disc 01).cue",
+                "/out/Artist/Album/Album (Disc 02).m3u8",
+                "/out/Artist/Album/Album (Disc 02).cue",
+            ]
+        );
+    }
+}
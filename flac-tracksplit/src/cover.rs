@@ -0,0 +1,348 @@
+use bytesize::ByteSize;
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::{debug, info, warn};
+
+use crate::{
+    Error, PictureBlock, Result,
+    picture::{BACK_COVER, FRONT_COVER},
+};
+
+/// Which of the disc image's pictures get embedded into each track,
+/// see [SplitOptions::pictures](crate::SplitOptions::pictures).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PicturePolicy {
+    /// Embed all pictures.
+    #[default]
+    All,
+
+    /// Embed only front cover pictures, if there are any.
+    FrontCover,
+
+    /// Embed no pictures.
+    None,
+
+    /// Embed only pictures whose PICTURE block (the image data plus a
+    /// few dozen bytes for its MIME type, description and dimensions)
+    /// is at most this many bytes large.
+    MaxSize(u64),
+}
+
+impl FromStr for PicturePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(PicturePolicy::All),
+            "front-cover" => Ok(PicturePolicy::FrontCover),
+            "none" => Ok(PicturePolicy::None),
+            _ => s
+                .strip_prefix("max-size=")
+                .and_then(|size| size.parse::<ByteSize>().ok())
+                .map(|size| PicturePolicy::MaxSize(size.as_u64()))
+                .ok_or_else(|| Error::UnknownOption {
+                    option: "picture policy",
+                    value: s.to_string(),
+                }),
+        }
+    }
+}
+
+impl fmt::Display for PicturePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PicturePolicy::All => f.write_str("all"),
+            PicturePolicy::FrontCover => f.write_str("front-cover"),
+            PicturePolicy::None => f.write_str("none"),
+            PicturePolicy::MaxSize(bytes) => write!(f, "max-size={}", bytes),
+        }
+    }
+}
+
+impl PicturePolicy {
+    /// The `pictures` to embed into each track, in order.
+    pub(crate) fn select(&self, pictures: Vec<PictureBlock>) -> Vec<PictureBlock> {
+        let total = pictures.len();
+        let selected: Vec<_> = pictures
+            .into_iter()
+            .filter(|picture| match self {
+                PicturePolicy::All => true,
+                PicturePolicy::FrontCover => picture.picture_type() == Some(FRONT_COVER),
+                PicturePolicy::None => false,
+                PicturePolicy::MaxSize(bytes) => picture.as_bytes().len() as u64 <= *bytes,
+            })
+            .collect();
+        if selected.len() < total {
+            debug!(
+                policy = %self,
+                embedded = selected.len(),
+                dropped = total - selected.len(),
+                "Not embedding all pictures."
+            );
+        }
+        selected
+    }
+}
+
+/// The names of cover files that get picked up next to a disc image
+/// without pictures, in order of preference. They are matched
+/// ignoring case.
+const COVER_FILE_NAMES: [&str; 6] = [
+    "cover.jpg",
+    "cover.jpeg",
+    "cover.png",
+    "folder.jpg",
+    "folder.jpeg",
+    "folder.png",
+];
+
+/// The path of the cover file next to the disc image at `input_path`,
+/// if there is one.
+fn cover_path(input_path: &Path) -> Result<Option<PathBuf>> {
+    let dir = match input_path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let input_io = |source| Error::InputIo {
+        path: dir.to_path_buf(),
+        source,
+    };
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(input_io)? {
+        let entry = entry.map_err(input_io)?;
+        if entry.file_type().map_err(input_io)?.is_file() {
+            names.push(entry.file_name());
+        }
+    }
+    Ok(COVER_FILE_NAMES.iter().find_map(|cover| {
+        names
+            .iter()
+            .find(|name| name.to_string_lossy().eq_ignore_ascii_case(cover))
+            .map(|name| dir.join(name))
+    }))
+}
+
+/// A front cover picture made from the cover file next to the disc
+/// image at `input_path`, if there is one.
+pub(crate) fn read_cover_file(input_path: &Path) -> Result<Option<PictureBlock>> {
+    let Some(path) = cover_path(input_path)? else {
+        return Ok(None);
+    };
+    let data = fs::read(&path).map_err(|source| Error::InputIo {
+        path: path.clone(),
+        source,
+    })?;
+    let (mime_type, dimensions) = if path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("png"))
+    {
+        ("image/png", png_dimensions(&data))
+    } else {
+        ("image/jpeg", jpeg_dimensions(&data))
+    };
+    if dimensions.is_none() {
+        warn!(
+            ?path,
+            mime_type, "Could not read the cover file's dimensions."
+        );
+    }
+    info!(?path, bytes = data.len(), "Using cover file.");
+    Ok(Some(PictureBlock::new(
+        FRONT_COVER,
+        mime_type,
+        dimensions.unwrap_or_default(),
+        &data,
+    )))
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]).into())
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+/// The width, height and bits per pixel of a PNG image, from its
+/// IHDR chunk.
+fn png_dimensions(data: &[u8]) -> Option<(u32, u32, u32)> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") || data.get(12..16)? != b"IHDR" {
+        return None;
+    }
+    let channels = match data.get(25)? {
+        0 | 3 => 1, // greyscale, palette
+        2 => 3,     // RGB
+        4 => 2,     // greyscale with alpha
+        6 => 4,     // RGB with alpha
+        _ => return None,
+    };
+    let bit_depth = u32::from(*data.get(24)?);
+    Some((be_u32(data, 16)?, be_u32(data, 20)?, bit_depth * channels))
+}
+
+/// The width, height and bits per pixel of a JPEG image, from its
+/// start of frame segment.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32, u32)> {
+    if !data.starts_with(&[0xff, 0xd8]) {
+        return None;
+    }
+    let mut offset = 2;
+    loop {
+        if *data.get(offset)? != 0xff {
+            return None;
+        }
+        let marker = *data.get(offset + 1)?;
+        match marker {
+            // fill bytes before a marker:
+            0xff => offset += 1,
+            // markers without a segment:
+            0x01 | 0xd0..=0xd7 => offset += 2,
+            // start of frame, other than DHT, JPG and DAC:
+            0xc0..=0xcf if ![0xc4, 0xc8, 0xcc].contains(&marker) => {
+                let precision = u32::from(*data.get(offset + 4)?);
+                let height = be_u16(data, offset + 5)?;
+                let width = be_u16(data, offset + 7)?;
+                let components = u32::from(*data.get(offset + 9)?);
+                return Some((width, height, precision * components));
+            }
+            // start of scan or end of image before any frame:
+            0xd9 | 0xda => return None,
+            _ => offset += 2 + be_u16(data, offset + 2)? as usize,
+        }
+    }
+}
+
+/// The file name extension of pictures of `mime_type`, if they are
+/// images of a common format.
+fn picture_extension(mime_type: &str) -> Option<&'static str> {
+    match mime_type.to_ascii_lowercase().as_str() {
+        "image/jpeg" | "image/jpg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/bmp" => Some("bmp"),
+        "image/tiff" => Some("tif"),
+        _ => None,
+    }
+}
+
+/// The file name (without extension) that a picture is extracted to:
+/// `cover` for front covers, `back` for back covers, and the picture
+/// type's name for the others, e.g. `lead-artist`.
+fn picture_file_stem(picture: &PictureBlock) -> String {
+    match (picture.picture_type(), picture.picture_type_name()) {
+        (Some(FRONT_COVER), _) => "cover".to_string(),
+        (Some(BACK_COVER), _) => "back".to_string(),
+        (_, Some(name)) => {
+            let mut stem = String::new();
+            for c in name.chars() {
+                if c.is_ascii_uppercase() && !stem.is_empty() {
+                    stem.push('-');
+                }
+                stem.push(c.to_ascii_lowercase());
+            }
+            stem
+        }
+        (_, None) => "picture".to_string(),
+    }
+}
+
+/// The file names that `pictures` are extracted to in the album
+/// directory, along with the image data. Later pictures whose names
+/// are taken get a number appended, e.g. `leaflet-2.jpg`; pictures
+/// that aren't images of a common format (e.g. links to images) are
+/// skipped.
+pub(crate) fn picture_files(pictures: &[PictureBlock]) -> Vec<(String, &[u8])> {
+    let mut files: Vec<(String, &[u8])> = vec![];
+    for picture in pictures {
+        let mime_type = picture.mime_type().unwrap_or_default();
+        let (Some(extension), Some(data)) = (picture_extension(&mime_type), picture.data()) else {
+            warn!(?picture, "Not extracting picture of an unknown format.");
+            continue;
+        };
+        let stem = picture_file_stem(picture);
+        let name = (1..)
+            .map(|n| match n {
+                1 => format!("{}.{}", stem, extension),
+                n => format!("{}-{}.{}", stem, n, extension),
+            })
+            .find(|name| !files.iter().any(|(taken, _)| taken == name))
+            .expect("some file name is free");
+        files.push((name, data));
+    }
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn picture(picture_type: u32, mime_type: &str, data: &[u8]) -> PictureBlock {
+        PictureBlock::new(picture_type, mime_type, (0, 0, 0), data)
+    }
+
+    #[test]
+    fn policies() {
+        let pictures = vec![
+            picture(0, "image/png", &[0; 100]),
+            picture(FRONT_COVER, "image/jpeg", &[0; 10]),
+        ];
+        let selected = |policy: &str| {
+            let policy: PicturePolicy = policy.parse().unwrap();
+            assert_eq!(policy.to_string().parse::<PicturePolicy>().unwrap(), policy);
+            policy.select(pictures.clone())
+        };
+        assert_eq!(selected("all"), pictures);
+        assert_eq!(selected("front-cover"), &pictures[1..]);
+        assert_eq!(selected("none"), []);
+        assert_eq!(selected("max-size=100"), &pictures[1..]);
+        assert_eq!(selected("max-size=1KiB"), pictures);
+        assert!(PicturePolicy::from_str("max-size=lots").is_err());
+    }
+
+    #[test]
+    fn dimensions() {
+        let mut png = b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR".to_vec();
+        png.extend([0, 0, 2, 0, 0, 0, 1, 0x2c, 8, 6, 0, 0, 0]);
+        assert_eq!(png_dimensions(&png), Some((512, 300, 32)));
+
+        let jpeg = [
+            0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xc4, 0, 2, 0xff, 0xc0, 0, 11, 8, 1, 0x2c, 2,
+            0, 3, 1, 0x22, 0,
+        ];
+        assert_eq!(jpeg_dimensions(&jpeg), Some((512, 300, 24)));
+        assert_eq!(jpeg_dimensions(&jpeg[..14]), None);
+    }
+
+    #[test]
+    fn file_names() {
+        let pictures = [
+            picture(FRONT_COVER, "image/jpeg", &[1]),
+            picture(5, "image/png", &[2]),
+            picture(5, "image/png", &[3]),
+            picture(7, "image/JPEG", &[4]),
+            picture(42, "image/gif", &[5]),
+            picture(FRONT_COVER, "-->", b"http://example.com/cover.jpg"),
+        ];
+        let names: Vec<_> = picture_files(&pictures)
+            .into_iter()
+            .map(|(name, data)| (name, data[0]))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                ("cover.jpg", 1),
+                ("leaflet.png", 2),
+                ("leaflet-2.png", 3),
+                ("lead-artist.jpg", 4),
+                ("picture.gif", 5),
+            ]
+            .map(|(name, data)| (name.to_string(), data))
+        );
+    }
+}
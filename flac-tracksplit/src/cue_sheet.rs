@@ -0,0 +1,293 @@
+use metaflac::block::CueSheet;
+use std::{fmt::Write, path::Path};
+use symphonia_core::meta::Value;
+
+use crate::{
+    END_OF_STREAM_TS, LEAD_OUT_TRACK_NUMBER, SplitPlan, SplitReport, Track,
+    playlist::relative_location,
+};
+
+/// CUE sheet positions are counted in frames of 1/75 seconds.
+const CUE_FRAMES_PER_SECOND: u64 = 75;
+
+fn tag<'a>(track: &'a Track, name: &str) -> Option<&'a str> {
+    match track.tag_value(name) {
+        Some(Value::String(value)) if !value.is_empty() => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+/// Make `s` fit into a quoted CUE sheet string, which can't contain
+/// quotes or line breaks.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('"', "'").replace(['\r', '\n'], " "))
+}
+
+/// A code from the CUESHEET block, or `None` if it's unset (empty or
+/// all zeros).
+fn code(s: &str) -> Option<&str> {
+    let s = s.trim_end_matches('\0').trim();
+    (!s.is_empty() && !s.chars().all(|c| c == '0')).then_some(s)
+}
+
+/// Format a position of `samples` as `MM:SS:FF`, rounding down to
+/// whole frames.
+fn position(samples: u64, sample_rate: u32) -> String {
+    let frames =
+        u128::from(samples) * u128::from(CUE_FRAMES_PER_SECOND) / u128::from(sample_rate.max(1));
+    let seconds = frames / u128::from(CUE_FRAMES_PER_SECOND);
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 60,
+        seconds % 60,
+        frames % u128::from(CUE_FRAMES_PER_SECOND)
+    )
+}
+
+/// Render a CUE sheet that describes `plan`'s tracks, one FILE each,
+/// to be written to a file in `dir`.
+///
+/// Every index point of the disc image's CUE `sheet` is listed in
+/// the file of the track it falls into, so that pregaps (INDEX 00)
+/// end up where they were split to: at the start of the track, or in
+/// a track 0 of their own before track 1. MCN and ISRCs are carried
+/// over from `sheet`, titles and performers from the tracks' tags.
+///
+/// Positions are relative to the first sample of each track's file,
+/// which `report` provides if the tracks have been split; as tracks
+/// are split at frame boundaries, they can be off by a few CUE
+/// frames.
+pub(crate) fn render_cue_sheet(
+    plan: &SplitPlan,
+    report: &SplitReport,
+    sheet: &CueSheet,
+    dir: &Path,
+) -> String {
+    let sample_rate = report.cue.sample_rate;
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "REM COMMENT {}",
+        quote(&format!(
+            "Generated by flac-tracksplit {} from {}",
+            env!("CARGO_PKG_VERSION"),
+            plan.input_path()
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+        ))
+    );
+    let first = plan.tracks().first().map(|planned| &planned.track);
+    if let Some(first) = first {
+        for (rem, name) in [("GENRE", "GENRE"), ("DATE", "DATE")] {
+            if let Some(value) = tag(first, name) {
+                let _ = writeln!(out, "REM {} {}", rem, quote(value));
+            }
+        }
+    }
+    if let Some(mcn) = code(&sheet.catalog_num) {
+        let _ = writeln!(out, "CATALOG {}", mcn);
+    }
+    if let Some(first) = first {
+        if let Some(artist) = tag(first, "ALBUMARTIST").or_else(|| tag(first, "ARTIST")) {
+            let _ = writeln!(out, "PERFORMER {}", quote(artist));
+        }
+        if let Some(album) = tag(first, "ALBUM") {
+            let _ = writeln!(out, "TITLE {}", quote(album));
+        }
+    }
+
+    // Every index point, in order, at its absolute position in the
+    // disc image:
+    let points: Vec<_> = sheet
+        .tracks
+        .iter()
+        .filter(|track| u32::from(track.number) != LEAD_OUT_TRACK_NUMBER)
+        .flat_map(|track| {
+            track
+                .indices
+                .iter()
+                .map(move |index| (track, index, track.offset + index.offset))
+        })
+        .collect();
+    let mut current_track = None;
+    for (t, planned) in plan.tracks().iter().enumerate() {
+        let track = &planned.track;
+        let first_sample = report
+            .tracks
+            .get(t)
+            .and_then(|track| track.first_sample)
+            .unwrap_or(track.start_ts);
+        let _ = writeln!(
+            out,
+            "FILE {} WAVE",
+            quote(&relative_location(dir, &planned.output_path))
+        );
+        let in_track = |ts: u64| {
+            ts >= track.start_ts && (track.end_ts == END_OF_STREAM_TS || ts < track.end_ts)
+        };
+        for (cue_track, index, ts) in points.iter().filter(|(_, _, ts)| in_track(*ts)) {
+            if current_track != Some(cue_track.number) {
+                current_track = Some(cue_track.number);
+                let _ = writeln!(out, "  TRACK {:02} AUDIO", cue_track.number);
+                // Tags of the track this CUE track was split into:
+                let tagged = plan
+                    .tracks()
+                    .iter()
+                    .find(|planned| planned.track.number == u32::from(cue_track.number))
+                    .map(|planned| &planned.track);
+                if let Some(tagged) = tagged {
+                    if let Some(title) = tag(tagged, "TITLE") {
+                        let _ = writeln!(out, "    TITLE {}", quote(title));
+                    }
+                    if let Some(artist) = tag(tagged, "ARTIST") {
+                        let _ = writeln!(out, "    PERFORMER {}", quote(artist));
+                    }
+                }
+                let isrc =
+                    code(&cue_track.isrc).or_else(|| tagged.and_then(|tagged| tag(tagged, "ISRC")));
+                if let Some(isrc) = isrc {
+                    let _ = writeln!(out, "    ISRC {}", isrc);
+                }
+                if cue_track.pre_emphasis {
+                    let _ = writeln!(out, "    FLAGS PRE");
+                }
+            }
+            let _ = writeln!(
+                out,
+                "    INDEX {:02} {}",
+                index.point_num,
+                position(ts.saturating_sub(first_sample), sample_rate)
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{SplitOptions, report::CueSummary};
+    use metaflac::block::{CueSheetTrack, CueSheetTrackIndex, StreamInfo};
+    use symphonia_core::meta::Tag;
+
+    /// A disc of two tracks, one second each, the first of which has
+    /// a one second pregap.
+    fn sheet() -> CueSheet {
+        let track = |number, offset, isrc: &str, indices: &[(u8, u64)]| CueSheetTrack {
+            offset,
+            number,
+            isrc: isrc.to_string(),
+            indices: indices
+                .iter()
+                .map(|&(point_num, offset)| CueSheetTrackIndex { offset, point_num })
+                .collect(),
+            ..CueSheetTrack::new()
+        };
+        CueSheet {
+            catalog_num: "1234567890123".to_string(),
+            tracks: vec![
+                track(1, 0, "", &[(0, 0), (1, 44100)]),
+                track(2, 88200, "GBXYZ2400002", &[(1, 0)]),
+                track(170, 132300, "", &[]),
+            ],
+            ..CueSheet::new()
+        }
+    }
+
+    /// Render the CUE sheet for tracks numbered and starting as given,
+    /// each ending where the next one starts.
+    fn render(tracks: &[(u32, u64)]) -> String {
+        let mut info = StreamInfo::new();
+        info.sample_rate = 44100;
+        info.total_samples = 132300;
+        let tracks = tracks
+            .iter()
+            .enumerate()
+            .map(|(i, &(number, start_ts))| {
+                let end_ts = tracks.get(i + 1).map_or(132300, |&(_, next)| next);
+                let tags = [
+                    ("ALBUM", "Album".to_string()),
+                    ("ARTIST", "Artist".to_string()),
+                    ("TITLE", format!("Title {}", number)),
+                    ("ISRC", format!("USABC240000{}", number)),
+                ]
+                .into_iter()
+                .map(|(key, value)| Tag::new(None, key, Value::String(value)))
+                .collect();
+                Track::new(&info, number, start_ts, end_ts, tags, vec![]).unwrap()
+            })
+            .collect();
+        let cue = CueSummary::new(&info, &[]);
+        let plan = SplitPlan::new(
+            Path::new("image.flac"),
+            &SplitOptions::new("/out"),
+            cue,
+            tracks,
+        )
+        .unwrap();
+        render_cue_sheet(
+            &plan,
+            &plan.report(),
+            &sheet(),
+            Path::new("/out/Artist/Album"),
+        )
+    }
+
+    #[test]
+    fn pregaps_codes_and_tags() {
+        let header = [
+            "CATALOG 1234567890123",
+            "PERFORMER \"Artist\"",
+            "TITLE \"Album\"",
+        ];
+        // Track 1's ISRC comes from its tags, track 2's from the sheet:
+        let track_2 = [
+            "FILE \"02.Title 2.flac\" WAVE",
+            "  TRACK 02 AUDIO",
+            "    TITLE \"Title 2\"",
+            "    PERFORMER \"Artist\"",
+            "    ISRC GBXYZ2400002",
+            "    INDEX 01 00:00:00",
+        ];
+
+        // The pregap stays with track 1:
+        let rendered = render(&[(1, 0), (2, 88200)]);
+        let lines: Vec<_> = rendered.lines().collect();
+        assert!(lines[0].starts_with("REM COMMENT \"Generated by flac-tracksplit"));
+        let track_1 = [
+            "FILE \"01.Title 1.flac\" WAVE",
+            "  TRACK 01 AUDIO",
+            "    TITLE \"Title 1\"",
+            "    PERFORMER \"Artist\"",
+            "    ISRC USABC2400001",
+            "    INDEX 00 00:00:00",
+            "    INDEX 01 00:01:00",
+        ];
+        assert_eq!(lines[1..], [&header[..], &track_1, &track_2].concat());
+
+        // The pregap is split into track 0:
+        let rendered = render(&[(0, 0), (1, 44100), (2, 88200)]);
+        let lines: Vec<_> = rendered.lines().collect();
+        let tracks_0_1 = [
+            "FILE \"00.Title 0.flac\" WAVE",
+            "  TRACK 01 AUDIO",
+            "    TITLE \"Title 1\"",
+            "    PERFORMER \"Artist\"",
+            "    ISRC USABC2400001",
+            "    INDEX 00 00:00:00",
+            "FILE \"01.Title 1.flac\" WAVE",
+            "    INDEX 01 00:00:00",
+        ];
+        assert_eq!(lines[1..], [&header[..], &tracks_0_1, &track_2].concat());
+    }
+
+    #[test]
+    fn positions() {
+        assert_eq!(position(0, 44100), "00:00:00");
+        assert_eq!(position(588, 44100), "00:00:01");
+        assert_eq!(position(587, 44100), "00:00:00");
+        assert_eq!(position(44100 * 61 + 588 * 74, 44100), "01:01:74");
+        assert_eq!(position(48000 * 3600, 48000), "60:00:00");
+    }
+}
@@ -0,0 +1,170 @@
+use std::{io, path::PathBuf};
+use symphonia_core::units::TimeBase;
+
+use crate::LEAD_OUT_TRACK_NUMBER;
+
+/// The result of splitting operations, see [Error].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong planning and splitting a disc image.
+///
+/// Errors don't name the input file; callers know which file they
+/// passed in, and should add it for display (the command line tool
+/// does so via [anyhow::Context]).
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The input file could not be opened or read.
+    #[error("reading {path:?}")]
+    InputIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// The input file isn't a FLAC stream that can be split.
+    #[error("not a FLAC file: {reason}")]
+    NotFlac { reason: String },
+
+    /// The stream's timestamps aren't counted in samples.
+    #[error(
+        "track time base {time_base:?} should be a fraction 1/sample rate, with a sample rate of {sample_rate}"
+    )]
+    InvalidTimeBase {
+        time_base: Option<TimeBase>,
+        sample_rate: u32,
+    },
+
+    /// The input file has no embedded CUE sheet.
+    #[error("no embedded CUE sheet found")]
+    NoCueSheet,
+
+    /// A CUE sheet entry (possibly the lead-out) lies past the end of
+    /// the stream.
+    #[error(
+        "{} starts at sample {start_ts}, past the end of the stream ({total_samples} samples)",
+        describe_cue(*track)
+    )]
+    CueOutOfRange {
+        track: u32,
+        start_ts: u64,
+        total_samples: u64,
+    },
+
+    /// The CUE sheet's entries are out of order, overlap, or are
+    /// otherwise inconsistent.
+    #[error("invalid CUE sheet: {reason}")]
+    InvalidCueSheet { reason: String },
+
+    /// A track would end before it starts.
+    #[error("track {track} would end at sample {end_ts}, not after its start at sample {start_ts}")]
+    EmptyTrack {
+        track: u32,
+        start_ts: u64,
+        end_ts: u64,
+    },
+
+    /// A frame could not be read from the input stream.
+    #[error("reading the frame after sample {after_ts} (track {track} ends at sample {end_ts})")]
+    ReadFrame {
+        track: u32,
+        after_ts: u64,
+        end_ts: u64,
+        #[source]
+        source: symphonia_core::errors::Error,
+    },
+
+    /// A planned track starts before the one planned ahead of it ends.
+    #[error(
+        "track {track} starts at sample {start_ts}, before track {previous} ends at sample {previous_end_ts}"
+    )]
+    OverlappingTracks {
+        track: u32,
+        start_ts: u64,
+        previous: u32,
+        previous_end_ts: u64,
+    },
+
+    /// A frame could not be parsed or rewritten.
+    #[error("corrupt frame at sample {ts} in track {track}: {reason}")]
+    CorruptFrame { track: u32, ts: u64, reason: String },
+
+    /// No output path for a track fits into the configured
+    /// [PathLimits](crate::PathLimits).
+    #[error("output path {path:?} can not fit into {max_bytes} bytes")]
+    PathTooLong { path: PathBuf, max_bytes: usize },
+
+    /// Several tracks would be written to the same output path.
+    #[error("{} output path(s) would be written more than once: {description}", paths.len())]
+    OutputCollision {
+        paths: Vec<PathBuf>,
+        description: String,
+    },
+
+    /// An output file exists, and may not be replaced.
+    #[error("output file {path:?} already exists")]
+    OutputExists { path: PathBuf },
+
+    /// An output file or directory could not be written.
+    #[error("writing {path:?}")]
+    OutputIo {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    /// Splitting or joining was stopped by an
+    /// [Interrupt](crate::Interrupt).
+    #[error("interrupted")]
+    Interrupted,
+
+    /// A [PlanFile](crate::PlanFile) can't be turned into plans.
+    #[error("invalid plan file: {reason}")]
+    InvalidPlanFile { reason: String },
+
+    /// [JoinOptions::join](crate::JoinOptions::join) was given no
+    /// tracks.
+    #[error("no tracks to join")]
+    NothingToJoin,
+
+    /// A track can't be joined with the others into a disc image.
+    #[error("can't join {path:?}: {reason}")]
+    IncompatibleTrack { path: PathBuf, reason: String },
+
+    /// Splitting a joined disc image would not give back a track file
+    /// as it was.
+    #[error("splitting the joined image would not reproduce {path:?} byte for byte")]
+    JoinNotLossless { path: PathBuf },
+
+    /// A [TagRules](crate::TagRules) file can't be parsed.
+    #[error("invalid tag rules: {reason}")]
+    InvalidTagRules { reason: String },
+
+    /// A disc image's sidecar tag file (see
+    /// [SidecarTags](crate::SidecarTags)) can't be parsed.
+    #[error("invalid sidecar tag file {path:?}: {reason}")]
+    InvalidSidecar { path: PathBuf, reason: String },
+
+    /// A string did not name any of an option's values.
+    #[error("unknown {option} {value:?}")]
+    UnknownOption { option: &'static str, value: String },
+}
+
+fn describe_cue(track: u32) -> String {
+    if track == LEAD_OUT_TRACK_NUMBER {
+        "the lead-out".to_string()
+    } else {
+        format!("track {}", track)
+    }
+}
+
+impl Error {
+    /// Report an I/O error on the output at `path`.
+    pub(crate) fn output_io(path: impl Into<PathBuf>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.into();
+        move |source| match source.kind() {
+            io::ErrorKind::AlreadyExists => Error::OutputExists { path },
+            _ => Error::OutputIo { path, source },
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use metaflac::Block;
+use std::{
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read, Seek},
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{
+    Error,
+    blocks::{raw_block, read_raw_blocks},
+};
+
+/// What to do about output files that already exist, e.g. from a
+/// previous run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExistingPolicy {
+    /// Replace the existing file.
+    #[default]
+    Overwrite,
+
+    /// Leave the existing file alone.
+    Skip,
+
+    /// Refuse to split the file. [SplitPlan::execute](crate::SplitPlan::execute)
+    /// checks all of a plan's outputs before writing any of them.
+    Fail,
+
+    /// Only replace the existing file if its metadata (including the
+    /// amount of padding) or audio frames differ from what would be
+    /// written. They are compared byte for byte rather than by
+    /// checksum; files whose audio has a different length are told
+    /// apart without reading it.
+    IfChanged,
+}
+
+impl FromStr for ExistingPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(ExistingPolicy::Overwrite),
+            "skip" => Ok(ExistingPolicy::Skip),
+            "fail" => Ok(ExistingPolicy::Fail),
+            "if-changed" => Ok(ExistingPolicy::IfChanged),
+            _ => Err(Error::UnknownOption {
+                option: "policy for existing files",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for ExistingPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ExistingPolicy::Overwrite => "overwrite",
+            ExistingPolicy::Skip => "skip",
+            ExistingPolicy::Fail => "fail",
+            ExistingPolicy::IfChanged => "if-changed",
+        })
+    }
+}
+
+/// Returns whether the FLAC file at `path` exists and consists of
+/// exactly the metadata `blocks` (including padding) and `audio` frames.
+///
+/// Blocks and frames are compared byte by byte, so even reordered tags
+/// count as a change. The STREAMINFO block carries the track's sample
+/// count, so any change in track boundaries shows up as a difference.
+pub(crate) fn is_unchanged(path: &Path, blocks: &[Block], audio: &[u8]) -> io::Result<bool> {
+    let mut f = match File::open(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+        f => BufReader::new(f?),
+    };
+    let Ok(existing_blocks) = read_raw_blocks(&mut f) else {
+        return Ok(false);
+    };
+    let expected: Option<Vec<_>> = blocks.iter().map(raw_block).collect();
+    if expected.as_ref() != Some(&existing_blocks) {
+        return Ok(false);
+    }
+    let audio_len = f.get_ref().metadata()?.len() - f.stream_position()?;
+    if audio_len != audio.len() as u64 {
+        return Ok(false);
+    }
+
+    let mut remaining = audio;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = f.read(&mut buf)?;
+        if n == 0 {
+            return Ok(remaining.is_empty());
+        }
+        if n > remaining.len() || buf[..n] != remaining[..n] {
+            return Ok(false);
+        }
+        remaining = &remaining[n..];
+    }
+}
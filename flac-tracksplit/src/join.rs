@@ -0,0 +1,627 @@
+use metaflac::{
+    Block,
+    block::{CueSheet, CueSheetTrack, CueSheetTrackIndex, StreamInfo},
+};
+use std::{
+    fmt::Debug,
+    fs::File,
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    path::{Path, PathBuf},
+};
+use symphonia_core::{
+    formats::FormatReader,
+    meta::{Tag, Value},
+};
+use tracing::{info, instrument, warn};
+
+use crate::{
+    DEFAULT_METADATA_PADDING, Error, ExistingPolicy, FilesystemSink, Interrupt,
+    LEAD_OUT_TRACK_NUMBER, NumberTags, OffsetFrame, PictureBlock, Result, SplitOptions, Track,
+    TrackSink, TrackTagConvention,
+    blocks::{
+        PADDING_BLOCK_TYPE, PICTURE_BLOCK_TYPE, STREAMINFO_BLOCK_TYPE, VORBIS_COMMENT_BLOCK_TYPE,
+    },
+    plan::{open_flac, read_blocks},
+    track_tags::is_album_tag,
+    write_blocks,
+};
+
+/// Settings for joining tracks split from a disc image back into a
+/// single FLAC file with an embedded CUE sheet, built up from
+/// defaults.
+///
+/// ```no_run
+/// use flac_tracksplit::JoinOptions;
+///
+/// let written = JoinOptions::new().join(
+///     &["/music/Artist/2001 - Album/01.One.flac", "/music/Artist/2001 - Album/02.Two.flac"],
+///     "/archive/album.flac",
+/// )?;
+/// # Ok::<(), flac_tracksplit::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct JoinOptions {
+    metadata_padding: u32,
+    existing: ExistingPolicy,
+    verify: bool,
+    interrupt: Interrupt,
+}
+
+impl Default for JoinOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A track file to be joined.
+struct SourceTrack {
+    path: PathBuf,
+    number: u32,
+    info: StreamInfo,
+    tags: Vec<Tag>,
+    pictures: Vec<PictureBlock>,
+    /// The size of the file's padding block, if it has one.
+    padding: Option<u32>,
+}
+
+impl JoinOptions {
+    /// Options for joining tracks, with default settings.
+    pub fn new() -> Self {
+        Self {
+            metadata_padding: DEFAULT_METADATA_PADDING,
+            existing: ExistingPolicy::default(),
+            verify: true,
+            interrupt: Interrupt::new(),
+        }
+    }
+
+    /// The number of 0-bytes of padding to add after the joined
+    /// image's metadata blocks.
+    pub fn metadata_padding(mut self, metadata_padding: u32) -> Self {
+        self.metadata_padding = metadata_padding;
+        self
+    }
+
+    /// What happens to an output file that already exists.
+    pub fn existing(mut self, existing: ExistingPolicy) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Whether to check that splitting the joined image reproduces
+    /// every track file byte for byte before writing it, and fail with
+    /// [Error::JoinNotLossless] otherwise. On by default.
+    pub fn verify(mut self, verify: bool) -> Self {
+        self.verify = verify;
+        self
+    }
+
+    /// The handle that stops joins with these options, see
+    /// [Interrupt].
+    pub fn interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// Join the track files at `track_paths`, in order, into a disc
+    /// image at `output_path`. Returns whether the file was written.
+    ///
+    /// The tracks' frames are concatenated as they are, renumbered to
+    /// follow each other. Each track becomes an entry in the image's
+    /// CUE sheet; a track 0 (an hidden pregap, as split off before
+    /// track 1) becomes the INDEX 00 of the track after it. The tags
+    /// that all tracks start out with are kept as they are, the
+    /// remaining ones get suffixed with their track's number
+    /// (`TITLE[3]`, or `TITLE[0]` for a track 0 whose tags differ from
+    /// the next track's). Pictures are taken from the first track.
+    ///
+    /// Tracks are numbered by their `TRACKNUMBER` tag, or else by the
+    /// number their file name starts with (`03.Title.flac` or
+    /// `2-03.Title.flac`), or else consecutively.
+    #[instrument(skip(self, track_paths), err)]
+    pub fn join<P: AsRef<Path>, O: AsRef<Path> + Debug>(
+        &self,
+        track_paths: &[P],
+        output_path: O,
+    ) -> Result<bool> {
+        let output_path = output_path.as_ref();
+        if track_paths.is_empty() {
+            return Err(Error::NothingToJoin);
+        }
+        let mut tracks = vec![];
+        let mut audio = vec![];
+        let mut next_number = 0;
+        let mut variable_block_size = None;
+        let mut offsets = vec![];
+        for (t, path) in track_paths.iter().enumerate() {
+            let path = path.as_ref();
+            let previous = tracks.last().map(|track: &SourceTrack| track.number);
+            let (track, frame) =
+                read_track(path, previous, next_number, &mut audio, &self.interrupt)?;
+            let incompatible = |reason: String| Error::IncompatibleTrack {
+                path: path.to_path_buf(),
+                reason,
+            };
+            if let Some(first) = tracks.first() {
+                check_compatible(&first.info, &track.info).map_err(incompatible)?;
+            }
+            if *variable_block_size.get_or_insert(frame.is_variable_block_size())
+                != frame.is_variable_block_size()
+            {
+                return Err(incompatible(
+                    "its frames have a different blocking strategy".to_string(),
+                ));
+            }
+            let samples = frame.samples_processed();
+            if !frame.is_variable_block_size()
+                && t + 1 < track_paths.len()
+                && samples % u64::from(track.info.max_block_size.max(1)) != 0
+            {
+                return Err(incompatible(
+                    "it ends with a short frame, which can only end a stream of fixed-size blocks"
+                        .to_string(),
+                ));
+            }
+            if track.info.total_samples != 0 && track.info.total_samples != samples {
+                warn!(
+                    ?path,
+                    streaminfo = track.info.total_samples,
+                    frames = samples,
+                    "Track's STREAMINFO and frames disagree on its length."
+                );
+            }
+            let start = offsets.last().map_or(0, |(start, samples)| start + samples);
+            offsets.push((start, samples));
+            next_number = frame.next_number();
+            tracks.push(track);
+        }
+        if let [pregap] = &tracks[..]
+            && pregap.number == 0
+        {
+            return Err(Error::IncompatibleTrack {
+                path: pregap.path.clone(),
+                reason: "a pregap track 0 can't be joined without the track after it".to_string(),
+            });
+        }
+        let total_samples = offsets.last().map_or(0, |(start, samples)| start + samples);
+
+        let image = self.image(&tracks, &offsets, total_samples, &audio, output_path)?;
+        let mut sink = FilesystemSink::new(self.existing);
+        if !sink.should_write_file(output_path, &image) {
+            return Ok(false);
+        }
+        let mut f = sink
+            .create(output_path)
+            .map_err(Error::output_io(output_path))?;
+        f.write_all(&image).map_err(Error::output_io(output_path))?;
+        if self.verify {
+            verify(f.path(), &tracks)?;
+        }
+        self.interrupt.check()?;
+        sink.finish(output_path, f)
+            .map_err(Error::output_io(output_path))?;
+        info!(
+            ?output_path,
+            tracks = tracks.len(),
+            total_samples,
+            "Joined tracks."
+        );
+        Ok(true)
+    }
+
+    /// The joined image's FLAC data, given each track's start and
+    /// length in samples and their concatenated `audio` frames. Fails
+    /// if the metadata can't be encoded, as an error writing to
+    /// `output_path`.
+    fn image(
+        &self,
+        tracks: &[SourceTrack],
+        offsets: &[(u64, u64)],
+        total_samples: u64,
+        audio: &[u8],
+        output_path: &Path,
+    ) -> Result<Vec<u8>> {
+        let first = &tracks[0];
+        let mut info = first.info.clone();
+        info.min_frame_size = tracks
+            .iter()
+            .map(|t| t.info.min_frame_size)
+            .min()
+            .unwrap_or(0);
+        info.max_frame_size = tracks
+            .iter()
+            .map(|t| t.info.max_frame_size)
+            .max()
+            .unwrap_or(0);
+        for track in &tracks[1..] {
+            if track.pictures != first.pictures {
+                warn!(path = ?track.path, "Track's pictures differ from the first track's, dropping them.");
+            }
+            if track.padding != first.padding {
+                warn!(path = ?track.path, "Track's padding differs from the first track's.");
+            }
+        }
+        let joined = Track::new(
+            &info,
+            0,
+            0,
+            total_samples,
+            joined_tags(tracks),
+            first.pictures.clone(),
+        )?;
+        let mut blocks = joined.metadata_blocks(total_samples);
+        blocks.insert(
+            2,
+            Block::CueSheet(cue_sheet(tracks, offsets, total_samples)),
+        );
+        blocks.push(Block::Padding(self.metadata_padding));
+        let mut image = vec![];
+        write_blocks(&blocks, &mut image).map_err(Error::output_io(output_path))?;
+        image.extend_from_slice(audio);
+        Ok(image)
+    }
+}
+
+/// Read the track file at `path`, appending its frames to `audio`,
+/// numbered from `first_number` on, until `interrupt` stops it.
+/// `previous` is the number of the track before it, if any.
+fn read_track(
+    path: &Path,
+    previous: Option<u32>,
+    first_number: u64,
+    audio: &mut Vec<u8>,
+    interrupt: &Interrupt,
+) -> Result<(SourceTrack, OffsetFrame)> {
+    let (mut reader, info, _) = open_flac(path)?;
+    let tags = match reader.metadata().current() {
+        Some(current) => current.tags().to_vec(),
+        None => vec![],
+    };
+    let number = track_number(path, &tags).unwrap_or(previous.map_or(1, |n| n + 1));
+    let incompatible = |reason: String| Error::IncompatibleTrack {
+        path: path.to_path_buf(),
+        reason,
+    };
+    match previous {
+        Some(previous) if number <= previous => {
+            return Err(incompatible(format!(
+                "its track number {} doesn't follow track {}",
+                number, previous
+            )));
+        }
+        _ if number >= LEAD_OUT_TRACK_NUMBER => {
+            return Err(incompatible(format!(
+                "its track number {} can't be written to a CUE sheet",
+                number
+            )));
+        }
+        _ => {}
+    }
+
+    let mut padding = None;
+    let mut pictures = vec![];
+    for (block_type, contents) in read_blocks(path)? {
+        match block_type {
+            PADDING_BLOCK_TYPE => padding = Some(contents.len() as u32),
+            PICTURE_BLOCK_TYPE => pictures.push(PictureBlock::from_bytes(contents)),
+            STREAMINFO_BLOCK_TYPE | VORBIS_COMMENT_BLOCK_TYPE => {}
+            block_type => warn!(?path, block_type, "Dropping the track's metadata block."),
+        }
+    }
+
+    let mut frame = OffsetFrame::numbered_from(number, first_number);
+    let mut last_end = 0;
+    loop {
+        interrupt.check()?;
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            Err(symphonia_core::errors::Error::IoError(err))
+                if err.kind() == ErrorKind::UnexpectedEof =>
+            {
+                break;
+            }
+            Err(source) => {
+                return Err(Error::ReadFrame {
+                    track: number,
+                    after_ts: last_end,
+                    end_ts: info.total_samples,
+                    source,
+                });
+            }
+        };
+        last_end = packet.ts + packet.dur;
+        audio.extend(frame.process(packet)?);
+    }
+    if frame.samples_processed() == 0 {
+        return Err(incompatible("it holds no audio frames".to_string()));
+    }
+    let track = SourceTrack {
+        path: path.to_path_buf(),
+        number,
+        info,
+        tags,
+        pictures,
+        padding,
+    };
+    Ok((track, frame))
+}
+
+/// A track's number, from its `TRACKNUMBER` tag (which may be given
+/// as `3/12`), or else from the start of its file name.
+fn track_number(path: &Path, tags: &[Tag]) -> Option<u32> {
+    let tagged = tags
+        .iter()
+        .find(|tag| tag.key.eq_ignore_ascii_case("TRACKNUMBER"))
+        .and_then(|tag| match &tag.value {
+            Value::String(value) => value.split('/').next()?.trim().parse().ok(),
+            _ => None,
+        });
+    tagged.or_else(|| {
+        // As named by this tool: `[<disc>-]<number>.<title>.flac`
+        let name = path.file_name()?.to_str()?;
+        let (prefix, _) = name.split_once('.')?;
+        prefix.rsplit('-').next()?.parse().ok()
+    })
+}
+
+/// Check that a track's stream can follow the first track's.
+fn check_compatible(first: &StreamInfo, info: &StreamInfo) -> Result<(), String> {
+    let fields = [
+        ("sample rate", first.sample_rate, info.sample_rate),
+        (
+            "channels",
+            first.num_channels.into(),
+            info.num_channels.into(),
+        ),
+        (
+            "bits per sample",
+            first.bits_per_sample.into(),
+            info.bits_per_sample.into(),
+        ),
+        (
+            "minimum block size",
+            first.min_block_size.into(),
+            info.min_block_size.into(),
+        ),
+        (
+            "maximum block size",
+            first.max_block_size.into(),
+            info.max_block_size.into(),
+        ),
+    ];
+    for (name, first, this) in fields {
+        if first != this {
+            return Err(format!(
+                "its {} ({}) differs from the first track's ({})",
+                name, this, first
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn same_tag(a: &Tag, b: &Tag) -> bool {
+    a.key == b.key && a.value.to_string() == b.value.to_string()
+}
+
+fn same_tags(a: &[Tag], b: &[Tag]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_tag(a, b))
+}
+
+/// The joined image's tags: Those that every track (but a pregap track
+/// 0) starts out with, followed by the remaining ones of each track,
+/// suffixed with its number. Splitting the image gives each track its
+/// tags back, in order.
+///
+/// As a track's suffixed values of a key replace the unsuffixed ones,
+/// the common tags stop before the first key that any track has
+/// remaining values of.
+///
+/// A pregap track 0 gets split off with the tags of the track after
+/// it, unless the image has tags suffixed with `[0]`; so these are
+/// only written if its tags differ.
+fn joined_tags(tracks: &[SourceTrack]) -> Vec<Tag> {
+    let regular: Vec<&SourceTrack> = tracks.iter().filter(|track| track.number != 0).collect();
+    let mut common = match regular.first() {
+        None => 0,
+        Some(first) => first
+            .tags
+            .iter()
+            .enumerate()
+            .take_while(|(i, tag)| {
+                is_album_tag(&tag.key, &TrackTagConvention::ALL)
+                    && regular
+                        .iter()
+                        .all(|track| track.tags.get(*i).is_some_and(|other| same_tag(tag, other)))
+            })
+            .count(),
+    };
+    while let Some(overridden) = regular.first().and_then(|first| {
+        first.tags[..common].iter().position(|tag| {
+            tracks.iter().any(|track| {
+                track.tags.get(common..).is_some_and(|rest| {
+                    rest.iter()
+                        .any(|other| other.key.eq_ignore_ascii_case(&tag.key))
+                })
+            })
+        })
+    }) {
+        common = overridden;
+    }
+    let mut tags: Vec<Tag> = regular
+        .first()
+        .map(|first| first.tags[..common].to_vec())
+        .unwrap_or_default();
+    let mut suffixed = regular;
+    if let [pregap, next, ..] = tracks
+        && pregap.number == 0
+        && !same_tags(&pregap.tags, &next.tags)
+    {
+        if pregap.tags.len() >= common && same_tags(&pregap.tags[..common], &tags) {
+            suffixed.insert(0, pregap);
+        } else {
+            warn!(
+                path = ?pregap.path,
+                "Pregap track doesn't start out with the tags all other tracks do."
+            );
+        }
+    }
+    for track in &suffixed {
+        tags.extend(track.tags[common..].iter().map(|tag| {
+            let key = format!("{}[{}]", tag.key, track.number);
+            Tag::new(tag.std_key, &key, tag.value.clone())
+        }));
+    }
+    tags
+}
+
+/// A track's ISRC, if it is tagged with a valid one.
+fn isrc(track: &SourceTrack) -> Option<String> {
+    track.tags.iter().find_map(|tag| match &tag.value {
+        Value::String(isrc)
+            if tag.key.eq_ignore_ascii_case("ISRC")
+                && isrc.len() == 12
+                && isrc.chars().all(|c| c.is_ascii_alphanumeric()) =>
+        {
+            Some(isrc.to_string())
+        }
+        _ => None,
+    })
+}
+
+/// The joined image's CUE sheet, given each track's start and length
+/// in samples.
+///
+/// The lead-out is numbered 170 as on CDs, which is what splitting
+/// looks for, even though the CUE sheet isn't a CD's (its tracks
+/// needn't start on CD sectors).
+fn cue_sheet(tracks: &[SourceTrack], offsets: &[(u64, u64)], total_samples: u64) -> CueSheet {
+    let index = |point_num, offset| CueSheetTrackIndex { offset, point_num };
+    let mut sheet = CueSheet {
+        is_cd: false,
+        ..CueSheet::new()
+    };
+    let mut pregap = None;
+    for (track, &(start, samples)) in tracks.iter().zip(offsets) {
+        if track.number == 0 {
+            pregap = Some(samples);
+            continue;
+        }
+        let (offset, indices) = match pregap.take() {
+            Some(pregap) => (start - pregap, vec![index(0, 0), index(1, pregap)]),
+            None => (start, vec![index(1, 0)]),
+        };
+        sheet.tracks.push(CueSheetTrack {
+            offset,
+            number: track.number as u8,
+            isrc: isrc(track).unwrap_or_default(),
+            indices,
+            ..CueSheetTrack::new()
+        });
+    }
+    sheet.tracks.push(CueSheetTrack {
+        offset: total_samples,
+        number: LEAD_OUT_TRACK_NUMBER as u8,
+        ..CueSheetTrack::new()
+    });
+    sheet
+}
+
+/// Check that splitting the joined image at `image_path` gives back
+/// the track files byte for byte.
+fn verify(image_path: &Path, tracks: &[SourceTrack]) -> Result<()> {
+    let padding = tracks[0].padding.unwrap_or(0);
+    let plan = SplitOptions::new("")
+        .metadata_padding(padding)
+        .number_tags(NumberTags::Keep)
+        .plan(image_path)?;
+    let mut sink = ComparingSink {
+        expected: tracks,
+        next: 0,
+        mismatch: None,
+    };
+    plan.execute_into(&mut sink)?;
+    match sink.mismatch {
+        Some(path) => Err(Error::JoinNotLossless { path }),
+        None if sink.next != tracks.len() => Err(Error::JoinNotLossless {
+            path: tracks[sink.next].path.clone(),
+        }),
+        None => Ok(()),
+    }
+}
+
+/// Compares the tracks split from a joined image to the files they
+/// were joined from.
+struct ComparingSink<'a> {
+    expected: &'a [SourceTrack],
+    next: usize,
+    /// The first track file that didn't come out the same.
+    mismatch: Option<PathBuf>,
+}
+
+/// Compares a split track's data to a track file.
+struct Comparing {
+    expected: BufReader<File>,
+    matches: bool,
+}
+
+impl Write for Comparing {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.matches {
+            let mut expected = vec![0u8; buf.len()];
+            self.matches = self.expected.read_exact(&mut expected).is_ok() && expected == buf;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl TrackSink for ComparingSink<'_> {
+    type Writer = Comparing;
+
+    fn create(&mut self, _path: &Path) -> io::Result<Comparing> {
+        let track = self
+            .expected
+            .get(self.next)
+            .ok_or_else(|| io::Error::other("splitting gives more tracks than were joined"))?;
+        Ok(Comparing {
+            expected: BufReader::new(File::open(&track.path)?),
+            matches: true,
+        })
+    }
+
+    fn finish(&mut self, _path: &Path, mut writer: Comparing) -> io::Result<()> {
+        let at_end = writer.expected.fill_buf()?.is_empty();
+        if !(writer.matches && at_end) {
+            self.mismatch
+                .get_or_insert_with(|| self.expected[self.next].path.clone());
+        }
+        self.next += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn track_numbers() {
+        let number = |path: &str, tags: &[(&str, &str)]| {
+            let tags: Vec<Tag> = tags
+                .iter()
+                .map(|(key, value)| Tag::new(None, key, Value::String(value.to_string())))
+                .collect();
+            track_number(Path::new(path), &tags)
+        };
+        assert_eq!(number("A/2001 - B/03.Three.flac", &[]), Some(3));
+        assert_eq!(number("A/2001 - B/2-03.Three.flac", &[]), Some(3));
+        assert_eq!(number("A/2001 - B/00.One.flac", &[]), Some(0));
+        assert_eq!(number("03.flac", &[]), Some(3));
+        assert_eq!(number("Three.flac", &[]), None);
+        assert_eq!(number("03.Three.flac", &[("tracknumber", "4/12")]), Some(4));
+    }
+}
@@ -1,162 +1,127 @@
 use anyhow::{Context, bail};
 use int_conv::Truncate;
-use metaflac::{
-    Block,
-    block::{Picture, PictureType, StreamInfo, VorbisComment},
-};
-use more_asserts as ma;
+use metaflac::{Block, block::StreamInfo};
 use std::{
     borrow::Cow,
     fmt::Debug,
-    fs::{File, create_dir_all},
-    io::Write,
-    num::NonZeroU32,
+    io::{self, ErrorKind, Write},
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
 };
 use symphonia_bundle_flac::FlacReader;
 use symphonia_core::{
     checksum::{Crc8Ccitt, Crc16Ansi},
-    formats::{Cue, CuePoint, FormatReader, Packet},
-    io::{MediaSourceStream, Monitor, ReadBytes},
-    meta::{StandardVisualKey, Tag, Value, Visual},
+    formats::{Cue, FormatReader, Packet},
+    io::{Monitor, ReadBytes},
+    meta::{Tag, Value},
 };
-use tracing::{debug, info, instrument, warn};
+use tracing::{debug, instrument};
+
+mod blocks;
+mod collisions;
+mod cover;
+mod cue_sheet;
+mod error;
+mod existing;
+mod join;
+mod numbering;
+mod options;
+mod paths;
+mod picture;
+mod plan;
+mod plan_file;
+mod playlist;
+mod pregap;
+mod provenance;
+mod report;
+mod rules;
+mod sidecar;
+mod sink;
+mod tag_keys;
+mod track_tags;
+use blocks::VORBIS_COMMENT_BLOCK_TYPE;
+pub use collisions::{CollisionStrategy, colliding_plans, resolve_collisions};
+pub use cover::PicturePolicy;
+pub use error::{Error, Result};
+pub use existing::ExistingPolicy;
+pub use join::JoinOptions;
+pub use numbering::NumberTags;
+pub use options::{DEFAULT_METADATA_PADDING, SplitOptions};
+use paths::PathParts;
+pub use paths::{Naming, PathLimits};
+pub use picture::PictureBlock;
+pub use plan::{PlannedTrack, SplitPlan, plan_split};
+pub use plan_file::{PLAN_FILE_VERSION, PlanEntry, PlanFile, TagEntry, TrackEntry, VisualEntry};
+pub use playlist::PlaylistFormat;
+pub use pregap::PregapPolicy;
+pub use provenance::{BOUNDARY_POLICY, Provenance};
+pub use report::{CueSummary, SplitReport, TrackReport, TrackStatus};
+pub use rules::TagRules;
+pub use sidecar::SidecarTags;
+pub use sink::{FilesystemSink, TrackSink};
+pub use track_tags::{TrackTagConvention, select_track_tags};
+
+/// A handle to stop the splits and joins whose options it was given
+/// to as soon as possible, e.g. from a signal handler. They fail with
+/// [Error::Interrupted], removing any temporary files they were
+/// writing.
+///
+/// Clones of a handle share its state, and each run can be given a
+/// handle of its own, so interrupting one leaves others in the same
+/// process alone.
+#[derive(Debug, Clone, Default)]
+pub struct Interrupt(Arc<AtomicBool>);
 
-/// Detect if the current cue track has any pregaps, and return their end/start timestamps, if so.
-fn maybe_pregap(cue: &Cue) -> Option<CuePoint> {
-    if !cue.index == 1 || cue.points.len() == 1 {
-        return None;
+impl Interrupt {
+    /// A handle that wasn't interrupted yet.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Ask the splits and joins holding this handle to stop.
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns whether [Interrupt::interrupt] has been called.
+    pub fn is_interrupted(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.is_interrupted() {
+            return Err(Error::Interrupted);
+        }
+        Ok(())
     }
-    cue.points.last().cloned()
 }
 
-#[instrument(skip(base_path, metadata_padding), err)]
-pub fn split_one_file<P: AsRef<Path> + Debug, B: AsRef<Path> + Debug>(
+/// Split a FLAC file with an embedded CUE sheet into tracks below
+/// `base_path`, returning the paths of the files written.
+///
+/// This is a shorthand for [SplitOptions::split] with default options
+/// otherwise; use [SplitOptions] for more control.
+pub fn split_one_file<P: AsRef<Path> + Debug, B: AsRef<Path>>(
     input_path: P,
     base_path: B,
     metadata_padding: u32,
-) -> anyhow::Result<Vec<PathBuf>> {
-    let base_path = base_path.as_ref();
-    let file = File::open(&input_path).with_context(|| format!("opening {:?}", input_path))?;
-    let file_length = file.metadata().context("file metadata")?.len();
-    let mss = MediaSourceStream::new(Box::new(file), Default::default());
-    let mut reader =
-        FlacReader::try_new(mss, &Default::default()).context("could not create flac reader")?;
-    debug!("tracks: {:?}", reader.tracks());
-    let track = reader.default_track().context("no default track")?;
-    let data = match &track.codec_params.extra_data {
-        Some(it) => it,
-        _ => bail!("Unclear track codec params - Not a flac file?"),
-    };
-    let info = StreamInfo::from_bytes(data);
-    let cues = reader.cues().to_vec();
-    let time_base = track.codec_params.time_base.context("track time base")?;
-    if time_base.numer != 1 {
-        bail!(
-            "track time_base numerator should be a fraction like 1/44000, instead {:?}",
-            time_base
-        );
-    }
-    if time_base.denom != info.sample_rate {
-        bail!(
-            "track time_base denominator ({:?}) should be the same as the overall streaminfo ({:?})",
-            time_base,
-            info.sample_rate
-        );
-    }
-    // since we're sure that the sample rate is an even denominator of
-    // symphonia's TimeBase, we can assume that the time stamps are in
-    // samples:
-    let last_ts: u64 = info.total_samples;
-
-    let mut track_paths = vec![];
-    let mut cue_iter = cues.iter().peekable();
-    let mut audio_buffer = Vec::with_capacity(file_length.try_into().unwrap());
-    if cue_iter.peek().is_none() {
-        warn!(
-            action = "skipping",
-            remedy = "Use `metaflac --import-cuesheet-from` to add the sheet and make the file splittable.",
-            "No embedded CUE sheet found."
-        );
-        return Ok(track_paths);
-    }
-
-    // Detect pregap track 0:
-    let mut pregap_start_ts: Option<u64> = None;
-    if let Some(cue) = cue_iter.peek()
-        && let Some(pregap) = maybe_pregap(cue)
-    {
-        let pregap_track = {
-            let metadata = reader.metadata();
-            let current_metadata = metadata.current().context("track tags")?;
-            let tags = current_metadata.tags();
-            let visuals = current_metadata.visuals();
-            Track::from_tags(
-                &info,
-                cue,
-                pregap.start_offset_ts,
-                tags,
-                visuals,
-                None,
-                Some(0),
-            )
-        };
-        let pregap_path = pregap_track.write_to_file(
-            base_path,
-            &mut reader,
-            &mut audio_buffer,
-            metadata_padding,
-        )?;
-        debug!(number = pregap_track.number, output = ?pregap_path, "Pregap");
-        track_paths.push(pregap_path);
-        pregap_start_ts = Some(pregap.start_offset_ts);
-    }
-
-    // Handle regular tracks:
-    while let Some(cue) = cue_iter.next() {
-        audio_buffer.clear();
-        let next = cue_iter.peek();
-        let end_ts = match next {
-            None => last_ts, // no lead-out, fudge it.
-            Some(track) if track.index == LEAD_OUT_TRACK_NUMBER => {
-                // we have a lead-out, capture the whole in the last track.
-                let end_ts = track.start_ts;
-                cue_iter.next();
-                end_ts
-            }
-            Some(track) => track.start_ts,
-        };
-        let track = {
-            let metadata = reader.metadata();
-            let current_metadata = metadata.current().context("track tags")?;
-            let tags = current_metadata.tags();
-            let visuals = current_metadata.visuals();
-            Track::from_tags(
-                &info,
-                cue,
-                end_ts,
-                tags,
-                visuals,
-                pregap_start_ts.take(),
-                None,
-            )
-        };
-        debug!(number = track.number, output = ?track.pathname(), "Track");
-        track_paths.push(track.write_to_file(
-            base_path,
-            &mut reader,
-            &mut audio_buffer,
-            metadata_padding,
-        )?);
-    }
-    info!("Done with disc image");
-    Ok(track_paths)
+) -> Result<Vec<PathBuf>> {
+    SplitOptions::new(base_path)
+        .metadata_padding(metadata_padding)
+        .split(input_path)
 }
 
 /// The track number used to identify a lead-out track on a cue sheet.
 pub const LEAD_OUT_TRACK_NUMBER: u32 = 170;
 
+/// The [Track::end_ts] of a last track that runs until the end of the
+/// stream, in streams that don't record their total number of samples.
+pub const END_OF_STREAM_TS: u64 = u64::MAX;
+
 /// Metadata identifying a track in a FLAC file that has an embedded CUE sheet.
 #[derive(Clone)]
 pub struct Track {
@@ -165,7 +130,14 @@ pub struct Track {
     pub start_ts: u64,
     pub end_ts: u64,
     pub tags: Vec<Tag>,
-    pub visuals: Vec<Visual>,
+    /// The disc image's PICTURE blocks that the
+    /// [PicturePolicy] embeds, copied into the track as they are.
+    pub pictures: Vec<PictureBlock>,
+    /// Where the track was split from, to be recorded in its tags.
+    pub provenance: Option<Provenance>,
+    /// Things about the track worth a second look, noticed while
+    /// planning it, for its [TrackReport].
+    pub(crate) warnings: Vec<String>,
 }
 
 impl std::fmt::Debug for Track {
@@ -175,60 +147,90 @@ impl std::fmt::Debug for Track {
             .field("start_ts", &self.start_ts)
             .field("end_ts", &self.end_ts)
             .field("tags", &self.tags)
+            .field("provenance", &self.provenance)
             .finish()
     }
 }
 
 impl Track {
-    fn interesting_tag(name: &str) -> bool {
-        !name.ends_with(']') && name != "CUESHEET" && name != "LOG"
+    /// Create a [Track] numbered `number` from sample `start_ts` up
+    /// to `end_ts` of a stream, with exactly the given tags and
+    /// pictures, and no [Provenance].
+    ///
+    /// Fails if the track would end before it starts.
+    pub fn new(
+        streaminfo: &StreamInfo,
+        number: u32,
+        start_ts: u64,
+        end_ts: u64,
+        tags: Vec<Tag>,
+        pictures: Vec<PictureBlock>,
+    ) -> Result<Self> {
+        if end_ts <= start_ts {
+            return Err(Error::EmptyTrack {
+                track: number,
+                start_ts,
+                end_ts,
+            });
+        }
+        Ok(Self {
+            streaminfo: StreamInfo {
+                md5: [0u8; 16].to_vec(),
+                // unknown, until we know how many samples get written:
+                total_samples: if end_ts == END_OF_STREAM_TS {
+                    0
+                } else {
+                    end_ts - start_ts
+                },
+                ..streaminfo.clone()
+            },
+            number,
+            start_ts,
+            end_ts,
+            tags,
+            pictures,
+            provenance: None,
+            warnings: vec![],
+        })
     }
 
     /// Create a [Track] from a file's embedded FLAC&vorbis comments and CUE sheet.
+    ///
+    /// The track gets the album-level tags, and those of the CUE sheet
+    /// track in any of the `conventions` (e.g. suffixed with `[N]` for
+    /// track `N`), as selected by [select_track_tags].
+    ///
+    /// Fails if the track would end before it starts.
+    #[allow(clippy::too_many_arguments)]
     pub fn from_tags(
         streaminfo: &StreamInfo,
         cue: &Cue,
         end_ts: u64,
         tags: &[Tag],
-        visuals: &[Visual],
+        conventions: &[TrackTagConvention],
+        pictures: &[PictureBlock],
         start_ts: Option<u64>,
         track_number: Option<u32>,
-    ) -> Self {
+    ) -> Result<Self> {
         let start_ts = start_ts.unwrap_or(cue.start_ts);
-        let suffix = format!("[{}]", cue.index);
-        let tags = tags
-            .iter()
-            .filter_map(|tag| {
-                let tag_name = if tag.key.ends_with(&suffix) {
-                    Some(&tag.key[0..(tag.key.len() - suffix.len())])
-                } else if Self::interesting_tag(&tag.key) {
-                    Some(tag.key.as_str())
-                } else {
-                    None
-                };
-                tag_name.map(|key| Tag::new(tag.std_key, key, tag.value.clone()))
-            })
-            .collect();
-        let visuals = visuals.to_vec();
-        Self {
-            streaminfo: StreamInfo {
-                md5: [0u8; 16].to_vec(),
-                total_samples: (end_ts - start_ts),
-                ..streaminfo.clone()
-            },
-            number: track_number.unwrap_or(cue.index),
+        let number = track_number.unwrap_or(cue.index);
+        let tags = select_track_tags(tags, conventions, cue.index);
+        Self::new(
+            streaminfo,
+            number,
             start_ts,
             end_ts,
             tags,
-            visuals,
-        }
+            pictures.to_vec(),
+        )
     }
 
-    /// Return the tag value for a given tag name.
+    /// Return the (first) tag value for a given tag name, ignoring
+    /// case.
     pub fn tag_value(&self, name: &str) -> Option<&Value> {
         self.tags
             .iter()
-            .find(|tag| tag.key == name)
+            .find(|tag| tag.key.eq_ignore_ascii_case(name))
             .map(|found| &found.value)
     }
 
@@ -251,214 +253,412 @@ impl Track {
         }
     }
 
-    /// Return the output pathname for a track.
-    pub fn pathname(&self) -> PathBuf {
-        let mut buf = PathBuf::new();
-        if let Some(Value::String(artist)) = self.tag_value("ALBUMARTIST") {
-            buf.push(Self::sanitize_pathname(artist).as_ref());
+    pub(crate) fn path_parts(&self) -> PathParts {
+        let artist = if let Some(Value::String(artist)) = self.tag_value("ALBUMARTIST") {
+            Self::sanitize_pathname(artist).into_owned()
         } else if let Some(Value::String(artist)) = self.tag_value("ARTIST") {
-            buf.push(Self::sanitize_pathname(artist).as_ref());
+            Self::sanitize_pathname(artist).into_owned()
         } else {
-            buf.push("Unknown Artist");
-        }
+            "Unknown Artist".to_string()
+        };
 
-        if let Some(Value::String(album)) = self.tag_value("ALBUM") {
+        let album = if let Some(Value::String(album)) = self.tag_value("ALBUM") {
             if let Some(Value::String(year)) = self.tag_value("DATE") {
-                buf.push(format!("{} - {}", year, Self::sanitize_pathname(album)));
+                format!("{} - {}", year, Self::sanitize_pathname(album))
             } else {
-                buf.push(Self::sanitize_pathname(album).as_ref());
+                Self::sanitize_pathname(album).into_owned()
             }
         } else {
-            buf.push("Unknown Album");
-        }
+            "Unknown Album".to_string()
+        };
 
-        let disc_prefix = match (self.tag_value("DISCNUMBER"), self.tag_value("TOTALDISCS")) {
-            (Some(Value::String(disc)), Some(Value::String(disc_total)))
-                if usize::from_str(disc_total).map(|total| total > 1) == Ok(true) =>
-            {
-                if let Ok(discno) = usize::from_str(disc) {
-                    Some(format!("{:02}-", discno))
-                } else {
-                    Some(format!("{}-", disc))
-                }
-            }
+        let disc = match self.tag_value("DISCNUMBER") {
+            Some(Value::String(disc)) => Some(if let Ok(discno) = usize::from_str(disc) {
+                format!("{:02}", discno)
+            } else {
+                Self::sanitize_pathname(disc).into_owned()
+            }),
             _ => None,
         };
+        let show_disc = matches!(
+            self.tag_value("TOTALDISCS").or_else(|| self.tag_value("DISCTOTAL")),
+            Some(Value::String(disc_total)) if usize::from_str(disc_total).map(|total| total > 1) == Ok(true)
+        );
 
-        if let Some(Value::String(title)) = self.tag_value("TITLE") {
-            buf.push(format!(
-                "{}{:02}.{}.flac",
-                disc_prefix.as_deref().unwrap_or(""),
-                self.number,
-                Self::sanitize_pathname(title)
-            ));
-        } else {
-            buf.push(format!(
-                "{}{:02}.flac",
-                disc_prefix.as_deref().unwrap_or(""),
-                self.number,
-            ));
+        let title = match self.tag_value("TITLE") {
+            Some(Value::String(title)) => Some(Self::sanitize_pathname(title).into_owned()),
+            _ => None,
+        };
+        PathParts {
+            naming: Naming::default(),
+            artist,
+            album,
+            album_suffix: None,
+            disc,
+            show_disc,
+            number: format!("{:02}", self.number),
+            title,
+            suffix: None,
         }
-        buf
     }
 
-    /// Write a track's
+    /// Return the release's catalog number, if it is tagged.
+    pub(crate) fn catalog_number(&self) -> Option<Cow<'_, str>> {
+        match self.tag_value("CATALOGNUMBER") {
+            Some(Value::String(catalog)) => Some(Self::sanitize_pathname(catalog)),
+            _ => None,
+        }
+    }
+
+    /// Return the output pathname for a track.
+    pub fn pathname(&self) -> PathBuf {
+        self.path_parts().to_path()
+    }
+
+    /// Return the output pathname for a track below `base_path`,
+    /// truncating the artist, album and title such that the path fits
+    /// into the given `limits`. Each truncation is logged as a warning.
+    pub fn limited_pathname(&self, base_path: &Path, limits: &PathLimits) -> Result<PathBuf> {
+        let relative = self
+            .path_parts()
+            .to_limited_path(base_path, limits, &mut vec![])?;
+        Ok(base_path.join(relative))
+    }
+
+    /// Return a track's
     /// [STREAM](https://xiph.org/flac/format.html#stream) metadata
     /// blocks - first STREAMINFO, then the remainder containing
-    /// pictures and vorbis comments.
+    /// vorbis comments and pictures.
+    ///
+    /// The VORBIS_COMMENT block is returned as a [Block::Unknown]
+    /// holding its serialized contents, so that the comments keep the
+    /// order of the track's tags (including all values of keys tagged
+    /// more than once), followed by its [Provenance], which replaces
+    /// any provenance tags the track had already.
+    pub fn metadata_blocks(&self, total_samples: u64) -> Vec<Block> {
+        let comment = vorbis_comment_block(VENDOR_STRING, &self.comments(total_samples));
+        let pictures = self.pictures.iter().map(PictureBlock::block);
+        let mut streaminfo = self.streaminfo.clone();
+        streaminfo.total_samples = total_samples;
+        vec![Block::StreamInfo(streaminfo), comment]
+            .into_iter()
+            .chain(pictures)
+            .collect()
+    }
+
+    /// The vorbis comments written to the track, in order: its tags,
+    /// followed by its [Provenance].
+    pub(crate) fn comments(&self, total_samples: u64) -> Vec<(&str, String)> {
+        let provenance: Vec<_> = self
+            .provenance
+            .iter()
+            .flat_map(|provenance| provenance.tags(self, total_samples))
+            .collect();
+        self.tags
+            .iter()
+            .filter(|tag| {
+                !provenance
+                    .iter()
+                    .any(|(key, _)| tag.key.eq_ignore_ascii_case(key))
+            })
+            .map(|tag| (tag.key.as_str(), tag.value.to_string()))
+            .chain(provenance.iter().map(|(key, value)| (*key, value.clone())))
+            .collect()
+    }
+
+    /// Write a track's
+    /// [STREAM](https://xiph.org/flac/format.html#stream) metadata
+    /// blocks (see [Track::metadata_blocks]), followed by padding.
     #[instrument(skip(self, to), fields(number = self.number, path = ?self.pathname()), err)]
     pub fn write_metadata<S: Write>(
         &self,
         total_samples: u64,
         metadata_padding: u32,
-        mut to: S,
-    ) -> anyhow::Result<()> {
-        to.write_all(b"fLaC")?;
-        let comment = VorbisComment {
-            vendor_string: "flac-tracksplit".to_string(),
-            comments: self
-                .tags
-                .iter()
-                .map(|tag| (tag.key.to_string(), vec![tag.value.to_string()]))
-                .collect(),
-        };
-        let pictures: Vec<Block> = self
-            .visuals
-            .iter()
-            .map(|visual| {
-                Block::Picture(Picture {
-                    picture_type: translate_visual_key(
-                        visual.usage.unwrap_or(StandardVisualKey::OtherIcon),
-                    ),
-                    mime_type: visual.media_type.to_string(),
-                    description: "".to_string(),
-                    width: visual.dimensions.map(|s| s.width).unwrap_or(0),
-                    height: visual.dimensions.map(|s| s.height).unwrap_or(0),
-                    depth: visual.bits_per_pixel.map(NonZeroU32::get).unwrap_or(0),
-                    num_colors: match visual.color_mode {
-                        Some(symphonia_core::meta::ColorMode::Discrete) => 0,
-                        Some(symphonia_core::meta::ColorMode::Indexed(n)) => n.get(),
-                        None => 0,
-                    },
-                    data: visual.data.to_vec(),
-                })
-            })
-            .collect();
-        let mut streaminfo = self.streaminfo.clone();
-        if total_samples != streaminfo.total_samples {
-            // This is a pretty peaceful condition (difference is
-            // about less than 1/10s), but let's let curious users
-            // know since it's the one thing that is "imprecise" about
-            // how this tool operates.
-            debug!(
-                inferred = streaminfo.total_samples,
-                actual = total_samples,
-                duration_diff_s = (streaminfo.total_samples as f32 - total_samples as f32)
-                    / (streaminfo.sample_rate as f32),
-                "inferred and actual total samples differ."
-            );
-        }
-        streaminfo.total_samples = total_samples;
-        let headers = vec![Block::StreamInfo(streaminfo), Block::VorbisComment(comment)];
-        for block in headers.into_iter().chain(pictures.into_iter()) {
-            block
-                .write_to(false, &mut to)
-                .with_context(|| format!("writing block {:?}", block))?;
-        }
-        Block::Padding(metadata_padding)
-            .write_to(true, &mut to)
-            .context("writing padding")?;
-        Ok(())
+        to: S,
+    ) -> io::Result<()> {
+        let mut blocks = self.metadata_blocks(total_samples);
+        blocks.push(Block::Padding(metadata_padding));
+        write_blocks(&blocks, to)
     }
 
     /// Write a STREAM's
     /// [FRAME](https://xiph.org/flac/format.html#frame) sequence,
     /// containing compressed audio samples. Returns the number of samples actually processed.
+    ///
+    /// Frames that end before the track starts are skipped; the frame
+    /// containing the track's last sample is the last one written. A
+    /// frame holding the track's first sample is written even if it
+    /// starts earlier, unless an earlier track read it from `from`
+    /// already. ([SplitPlan](crate::SplitPlan)s assign such frames to
+    /// the earlier track, whether it is written or not, see
+    /// [BOUNDARY_POLICY].)
+    ///
+    /// Errors writing to `to` are reported as [Error::OutputIo] on
+    /// `path`.
     #[instrument(skip(self, from, to), fields(number = self.number, path = ?self.pathname()), err)]
-    pub fn write_audio<S: Write>(&self, from: &mut FlacReader, mut to: S) -> anyhow::Result<u64> {
+    pub fn write_audio<S: Write>(&self, from: &mut FlacReader, to: S, path: &Path) -> Result<u64> {
+        self.copy_audio(from, to, path, self.start_ts, &Interrupt::new())
+            .map(|copied| copied.samples)
+    }
+
+    /// Copy the track's frames, see [Track::write_audio], returning
+    /// the range of samples they cover.
+    ///
+    /// Frames that start before the track, but not before
+    /// `image_start_ts` (where the disc image's first track starts),
+    /// belong to an earlier track, and are skipped. Fails with
+    /// [Error::Interrupted] once `interrupt` is.
+    fn copy_audio<S: Write>(
+        &self,
+        from: &mut FlacReader,
+        mut to: S,
+        path: &Path,
+        image_start_ts: u64,
+        interrupt: &Interrupt,
+    ) -> Result<CopiedAudio> {
         // TODO: Seek to the track start. Currently, this is only
         // called in sequence (we're parallel per-file), so no need to
         // do that rn, but it would be nice!
 
         let mut last_end: u64 = 0;
-        let mut frame = OffsetFrame::default();
+        let mut first_ts = None;
+        let mut frame = OffsetFrame::new(self.number);
         loop {
-            let packet = from
-                .next_packet()
-                .with_context(|| format!("last end: {:?} vs {:?}", last_end, self.end_ts))?;
+            interrupt.check()?;
+            let packet = match from.next_packet() {
+                Ok(packet) => packet,
+                Err(symphonia_core::errors::Error::IoError(err))
+                    if err.kind() == ErrorKind::UnexpectedEof
+                        && self.end_ts == END_OF_STREAM_TS
+                        && frame.samples_processed > 0 =>
+                {
+                    return Ok(CopiedAudio {
+                        first_ts: first_ts.unwrap_or(self.start_ts),
+                        samples: frame.samples_processed,
+                    });
+                }
+                Err(source) => {
+                    return Err(Error::ReadFrame {
+                        track: self.number,
+                        after_ts: last_end,
+                        end_ts: self.end_ts,
+                        source,
+                    });
+                }
+            };
 
             let ts = packet.ts;
             let dur = packet.dur;
-            ma::assert_ge!(
-                ts,
-                self.start_ts,
-                "Packet timestamp is not >= this track's start ts. Potential bug exposed by the previous track.",
-            );
+            if ts + dur <= self.start_ts || (image_start_ts..self.start_ts).contains(&ts) {
+                // This frame belongs to an earlier track that isn't
+                // being written, e.g. because it was removed from the
+                // plan:
+                last_end = ts + dur;
+                continue;
+            }
 
             // Adjust the frame header:
             // * Adjust sample/frame number such that each track starts at frame/sample 0. This should fix seeking.
             // * Recompute the 8-bit header CRC
             // * Recompute the 16-bit footer CRC
 
-            let updated_buf = frame
-                .process(packet)
-                .with_context(|| format!("processing frame at ts {}", ts))?;
-            to.write_all(&updated_buf)?;
+            let updated_buf = frame.process(packet)?;
+            to.write_all(&updated_buf).map_err(Error::output_io(path))?;
+            first_ts.get_or_insert(ts);
 
             last_end = ts + dur;
             if last_end >= self.end_ts {
-                return Ok(frame.samples_processed);
+                return Ok(CopiedAudio {
+                    first_ts: first_ts.unwrap_or(self.start_ts),
+                    samples: frame.samples_processed,
+                });
             }
         }
     }
 
+    /// Write the track to the file at `path`, creating its parent
+    /// directories as needed. If a file already exists at `path`,
+    /// `existing` decides what happens to it.
+    ///
+    /// Returns whether the file was written.
     pub fn write_to_file(
         &self,
-        base_path: &Path,
+        path: &Path,
+        existing: ExistingPolicy,
+        reader: &mut FlacReader,
+        audio_buffer: &mut Vec<u8>,
+        metadata_padding: u32,
+    ) -> Result<bool> {
+        self.write_to_sink(
+            path,
+            &mut FilesystemSink::new(existing),
+            reader,
+            audio_buffer,
+            metadata_padding,
+        )
+    }
+
+    /// Write the track, planned to go to `path`, into `sink`.
+    ///
+    /// Returns whether the sink wanted the track written.
+    pub fn write_to_sink<S: TrackSink>(
+        &self,
+        path: &Path,
+        sink: &mut S,
+        reader: &mut FlacReader,
+        audio_buffer: &mut Vec<u8>,
+        metadata_padding: u32,
+    ) -> Result<bool> {
+        self.write_into(
+            path,
+            sink,
+            reader,
+            audio_buffer,
+            metadata_padding,
+            self.start_ts,
+            &Interrupt::new(),
+        )
+        .map(|output| output.written)
+    }
+
+    /// Write the track into `sink`, see [Track::write_to_sink], and
+    /// return what was written. Frames are assigned to tracks as in
+    /// [Track::copy_audio], which `interrupt` stops.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn write_into<S: TrackSink>(
+        &self,
+        path: &Path,
+        sink: &mut S,
         reader: &mut FlacReader,
         mut audio_buffer: &mut Vec<u8>,
         metadata_padding: u32,
-    ) -> anyhow::Result<PathBuf> {
-        let pathbuf = base_path.join(self.pathname());
-        let path = &pathbuf;
-        if let Some(parent) = path.parent() {
-            create_dir_all(parent).context("creating album dir")?;
+        image_start_ts: u64,
+        interrupt: &Interrupt,
+    ) -> Result<TrackOutput> {
+        let audio = self.copy_audio(reader, &mut audio_buffer, path, image_start_ts, interrupt)?;
+        if self.end_ts == END_OF_STREAM_TS {
+            debug!(
+                track = self.number,
+                total_samples = audio.samples,
+                "Track ran until the end of the stream."
+            );
+        } else if audio.samples != self.streaminfo.total_samples {
+            // This is a pretty peaceful condition (difference is
+            // about less than 1/10s), but let's let curious users
+            // know since it's the one thing that is "imprecise" about
+            // how this tool operates.
+            debug!(
+                inferred = self.streaminfo.total_samples,
+                actual = audio.samples,
+                duration_diff_s = (self.streaminfo.total_samples as f32 - audio.samples as f32)
+                    / (self.streaminfo.sample_rate as f32),
+                "inferred and actual total samples differ."
+            );
+        }
+        let mut blocks = self.metadata_blocks(audio.samples);
+        blocks.push(Block::Padding(metadata_padding));
+        let mut output = TrackOutput {
+            audio,
+            tags: self
+                .comments(audio.samples)
+                .into_iter()
+                .map(|(key, value)| TagEntry {
+                    key: key.to_string(),
+                    value,
+                })
+                .collect(),
+            written: false,
+            bytes: 0,
+        };
+        if !sink
+            .should_write(path, &blocks, audio_buffer)
+            .map_err(Error::output_io(path))?
+        {
+            return Ok(output);
         }
-        let mut f = File::create(path).unwrap();
-        let sample_count = self
-            .write_audio(reader, &mut audio_buffer)
-            .with_context(|| format!("buffering track {:?} audio", path))?;
 
-        self.write_metadata(sample_count, metadata_padding, &mut f)
-            .with_context(|| format!("writing track {:?}", path))?;
-        f.write_all(audio_buffer)
-            .with_context(|| format!("writing track {:?} audio", path))?;
-        Ok(pathbuf)
+        interrupt.check()?;
+        let mut metadata = vec![];
+        write_blocks(&blocks, &mut metadata).map_err(Error::output_io(path))?;
+        let mut writer = sink.create(path).map_err(Error::output_io(path))?;
+        writer
+            .write_all(&metadata)
+            .and_then(|()| writer.write_all(audio_buffer))
+            .map_err(Error::output_io(path))?;
+        interrupt.check()?;
+        sink.finish(path, writer).map_err(Error::output_io(path))?;
+        output.written = true;
+        output.bytes = (metadata.len() + audio_buffer.len())
+            .try_into()
+            .unwrap_or(u64::MAX);
+        Ok(output)
     }
 }
 
-fn translate_visual_key(key: StandardVisualKey) -> PictureType {
-    use PictureType::*;
-    match key {
-        StandardVisualKey::FileIcon => Icon,
-        StandardVisualKey::OtherIcon => Other,
-        StandardVisualKey::FrontCover => CoverFront,
-        StandardVisualKey::BackCover => CoverBack,
-        StandardVisualKey::Leaflet => Leaflet,
-        StandardVisualKey::Media => Media,
-        StandardVisualKey::LeadArtistPerformerSoloist => LeadArtist,
-        StandardVisualKey::ArtistPerformer => Artist,
-        StandardVisualKey::Conductor => Conductor,
-        StandardVisualKey::BandOrchestra => Band,
-        StandardVisualKey::Composer => Composer,
-        StandardVisualKey::Lyricist => Lyricist,
-        StandardVisualKey::RecordingLocation => RecordingLocation,
-        StandardVisualKey::RecordingSession => DuringRecording,
-        StandardVisualKey::Performance => DuringPerformance,
-        StandardVisualKey::ScreenCapture => ScreenCapture,
-        StandardVisualKey::Illustration => Illustration,
-        StandardVisualKey::BandArtistLogo => BandLogo,
-        StandardVisualKey::PublisherStudioLogo => PublisherLogo,
+/// The samples that a track's frames cover: As tracks are split at
+/// frame boundaries, they may start before the track's `start_ts`, and
+/// end after its `end_ts`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct CopiedAudio {
+    pub(crate) first_ts: u64,
+    pub(crate) samples: u64,
+}
+
+/// What writing a track into a [TrackSink] did.
+#[derive(Debug, Clone)]
+pub(crate) struct TrackOutput {
+    pub(crate) audio: CopiedAudio,
+    /// The vorbis comments written to the track, see
+    /// [Track::metadata_blocks].
+    pub(crate) tags: Vec<TagEntry>,
+    /// Whether the sink wanted the track written.
+    pub(crate) written: bool,
+    /// The size of the FLAC data written, in bytes.
+    pub(crate) bytes: u64,
+}
+
+/// The vendor string of the vorbis comments written, naming the
+/// version that wrote them.
+const VENDOR_STRING: &str = concat!("flac-tracksplit ", env!("CARGO_PKG_VERSION"));
+
+/// Serialize a VORBIS_COMMENT block holding the `comments` in order.
+///
+/// metaflac's [VorbisComment](metaflac::block::VorbisComment) keeps
+/// comments in a hash map, which writes them in a different order
+/// every time, so the same tags wouldn't always result in the same
+/// file.
+fn vorbis_comment_block(vendor: &str, comments: &[(&str, String)]) -> Block {
+    fn put(data: &mut Vec<u8>, bytes: &[u8]) {
+        data.extend((bytes.len() as u32).to_le_bytes());
+        data.extend(bytes);
+    }
+    let mut data = vec![];
+    put(&mut data, vendor.as_bytes());
+    data.extend((comments.len() as u32).to_le_bytes());
+    for (key, value) in comments {
+        put(&mut data, format!("{}={}", key, value).as_bytes());
+    }
+    Block::Unknown((VORBIS_COMMENT_BLOCK_TYPE, data))
+}
+
+/// Write the FLAC stream marker, followed by `blocks`, the last of
+/// which is marked as such.
+fn write_blocks<S: Write>(blocks: &[Block], mut to: S) -> io::Result<()> {
+    to.write_all(b"fLaC")?;
+    for (i, block) in blocks.iter().enumerate() {
+        block
+            .write_to(i + 1 == blocks.len(), &mut to)
+            .map_err(metaflac_io_error)?;
+    }
+    Ok(())
+}
+
+/// Write errors from metaflac are I/O errors, except for (unlikely)
+/// encoding failures.
+fn metaflac_io_error(err: metaflac::Error) -> io::Error {
+    match err.kind {
+        metaflac::ErrorKind::Io(err) => err,
+        _ => io::Error::new(ErrorKind::InvalidData, err.description),
     }
 }
 
@@ -470,17 +670,74 @@ fn translate_visual_key(key: StandardVisualKey) -> PictureType {
 /// all the frames making up that track.
 #[derive(Default)]
 pub struct OffsetFrame {
+    track: u32,
     initial_offset: Option<u64>,
+    first_number: u64,
+    variable_block_size: bool,
+    frames_processed: u64,
     samples_processed: u64,
 }
 
 impl OffsetFrame {
+    /// Create an [OffsetFrame] for the frames of track number `track`,
+    /// which is used to report [Error::CorruptFrame]s.
+    pub fn new(track: u32) -> Self {
+        Self {
+            track,
+            ..Default::default()
+        }
+    }
+
+    /// Create an [OffsetFrame] whose first frame gets the frame/sample
+    /// number `first_number` instead of 0, e.g. to append the frames
+    /// to another stream's, see [OffsetFrame::next_number].
+    pub fn numbered_from(track: u32, first_number: u64) -> Self {
+        Self {
+            track,
+            first_number,
+            ..Default::default()
+        }
+    }
+
+    /// The number of samples in the frames processed so far.
+    pub fn samples_processed(&self) -> u64 {
+        self.samples_processed
+    }
+
+    /// The frame/sample number that a frame following the ones
+    /// processed so far would have: Streams with a fixed block size
+    /// number their frames, streams with a variable block size their
+    /// samples.
+    pub fn next_number(&self) -> u64 {
+        self.first_number
+            + if self.variable_block_size {
+                self.samples_processed
+            } else {
+                self.frames_processed
+            }
+    }
+
+    /// Returns whether the frames processed so far have a variable
+    /// block size (and so, are numbered by sample).
+    pub(crate) fn is_variable_block_size(&self) -> bool {
+        self.variable_block_size
+    }
+
     /// Processes a FLAC frame by rewriting its sample/frame offset
     /// and CRC checksums, and emits that frame in an updated byte
     /// buffer.
     ///
     /// Returns a byte buffer containing the updated frame.
-    pub fn process(&mut self, packet: Packet) -> anyhow::Result<Vec<u8>> {
+    pub fn process(&mut self, packet: Packet) -> Result<Vec<u8>> {
+        let ts = packet.ts;
+        self.rewrite(packet).map_err(|err| Error::CorruptFrame {
+            track: self.track,
+            ts,
+            reason: format!("{:#}", err),
+        })
+    }
+
+    fn rewrite(&mut self, packet: Packet) -> anyhow::Result<Vec<u8>> {
         let mut frame_reader = packet.as_buf_reader();
         let mut header_crc = Crc8Ccitt::new(0);
         let mut footer_crc = Crc16Ansi::new(0);
@@ -492,6 +749,8 @@ impl OffsetFrame {
         header_crc.process_double_bytes(sync_u8);
         footer_crc.process_double_bytes(sync_u8);
         frame_out.write_all(&sync_u8)?;
+        // The blocking strategy bit, set for variable block sizes:
+        let variable_block_size = sync & 1 == 1;
 
         // FLAC frame description
         let desc = frame_reader.read_be_u16().context("reading frame desc")?;
@@ -505,11 +764,20 @@ impl OffsetFrame {
         // Next up is the frame/sample number, here we munge some data:
         let (orig_sample_offset, _sample_n_bytes) =
             utf8_decode_be_u64(&mut frame_reader).context("decoding the sample offset")?;
-        if self.initial_offset.is_none() {
+        let initial_offset = *self.initial_offset.get_or_insert_with(|| {
             debug!(orig_sample_offset, "first sample offset");
-            self.initial_offset.replace(orig_sample_offset);
-        }
-        let sample_offset = orig_sample_offset - self.initial_offset.unwrap();
+            self.variable_block_size = variable_block_size;
+            orig_sample_offset
+        });
+        let sample_offset = orig_sample_offset
+            .checked_sub(initial_offset)
+            .with_context(|| {
+                format!(
+                    "frame/sample number {} lies before the track's first frame at {}",
+                    orig_sample_offset, initial_offset
+                )
+            })?
+            + self.first_number;
         let offset_u8 = utf8_encode_be_u64(sample_offset).context("encoding the new offset")?;
         header_crc.process_buf_bytes(&offset_u8);
         footer_crc.process_buf_bytes(&offset_u8);
@@ -520,21 +788,21 @@ impl OffsetFrame {
         // the `desc` fields).
         let block_samples: u64 = match block_size_enc & 0b1111 {
             0b0110 => {
-                // block size is given in the next 8 bits:
+                // block size (minus one) is given in the next 8 bits:
                 let bs_u8 = frame_reader.read_u8().context("8bit block size")?;
                 header_crc.process_byte(bs_u8);
                 footer_crc.process_byte(bs_u8);
                 frame_out.write_all(&[bs_u8])?;
-                bs_u8.into()
+                u64::from(bs_u8) + 1
             }
             0b0111 => {
-                // block size given in the next 16 bits:
+                // block size (minus one) given in the next 16 bits:
                 let bs = frame_reader.read_be_u16().context("8bit block size")?;
                 let bs_u8 = bs.to_be_bytes();
                 header_crc.process_double_bytes(bs_u8);
                 footer_crc.process_double_bytes(bs_u8);
                 frame_out.write_all(&bs_u8)?;
-                bs.into()
+                u64::from(bs) + 1
             }
             0b0001 => 192,
             0b0000 => bail!("reserved sample count"),
@@ -571,6 +839,9 @@ impl OffsetFrame {
 
         // Next, the subframes; we do not touch them, but we do rewrite the footer CRC:
         let remainder = frame_reader.read_buf_bytes_available_ref();
+        if remainder.len() < 2 {
+            bail!("frame is too short to hold a footer CRC");
+        }
         let subframes = &remainder[..remainder.len() - 2];
         footer_crc.process_buf_bytes(subframes);
         frame_out.write_all(subframes)?;
@@ -579,6 +850,7 @@ impl OffsetFrame {
         let my_footer_crc_u8 = my_footer_crc.to_be_bytes();
         frame_out.write_all(&my_footer_crc_u8)?;
         self.samples_processed += block_samples;
+        self.frames_processed += 1;
         Ok(frame_out)
     }
 }
@@ -638,7 +910,10 @@ fn utf8_encode_be_u64(input: u64) -> anyhow::Result<Vec<u8>> {
         39..=43 => 0b1111_1000,
         34..=38 => 0b1111_1100,
         29..=33 => 0b1111_1110,
-        00..=28 | 65..=u32::MAX => unreachable!("can't encode more than 7 leading bits"),
+        00..=28 | 65..=u32::MAX => bail!(
+            "can't encode the frame/sample number {} in 7 bytes of extended UTF-8",
+            input
+        ),
     };
     debug_assert!(
         64 - input.leading_zeros()
@@ -702,6 +977,160 @@ mod test {
         }
     }
 
+    #[test]
+    fn suffixed_tags_replace_album_tags() {
+        let tags: Vec<Tag> = [
+            ("ALBUM", "Album"),
+            ("ARTIST", "One"),
+            ("ARTIST", "Two"),
+            ("GENRE", "Rock"),
+            ("GENRE", "Pop"),
+            ("ARTIST[3]", "Three"),
+            ("ARTIST[3]", "Four"),
+            ("TITLE[3]", "Title"),
+            ("TITLE[13]", "Other"),
+        ]
+        .iter()
+        .map(|(key, value)| Tag::new(None, key, Value::String(value.to_string())))
+        .collect();
+        let cue = Cue {
+            index: 3,
+            start_ts: 0,
+            tags: vec![],
+            points: vec![],
+        };
+        let track = Track::from_tags(
+            &StreamInfo::new(),
+            &cue,
+            100,
+            &tags,
+            &TrackTagConvention::ALL,
+            &[],
+            None,
+            None,
+        )
+        .unwrap();
+        let tagged: Vec<_> = track
+            .tags
+            .iter()
+            .map(|tag| (tag.key.as_str(), tag.value.to_string()))
+            .collect();
+        assert_eq!(
+            tagged,
+            [
+                ("ALBUM", "Album"),
+                ("GENRE", "Rock"),
+                ("GENRE", "Pop"),
+                ("ARTIST", "Three"),
+                ("ARTIST", "Four"),
+                ("TITLE", "Title"),
+            ]
+            .map(|(key, value)| (key, value.to_string()))
+        );
+
+        // Without the suffix convention, only the album-level tags
+        // remain:
+        let track = Track::from_tags(
+            &StreamInfo::new(),
+            &cue,
+            100,
+            &tags,
+            &[TrackTagConvention::CueTrack],
+            &[],
+            None,
+            None,
+        )
+        .unwrap();
+        assert!(track.tag_value("TITLE").is_none());
+        assert_eq!(track.tags.len(), 5);
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        // A frame header cut short:
+        let mut frame = OffsetFrame::new(2);
+        let error = frame
+            .process(Packet::new_from_slice(0, 4096, 0, &[0xff, 0xf8, 0x79]))
+            .unwrap_err();
+        assert!(
+            matches!(
+                error,
+                Error::CorruptFrame {
+                    track: 2,
+                    ts: 4096,
+                    ..
+                }
+            ),
+            "{error:?}"
+        );
+
+        // A frame numbered before the track's first frame:
+        let frame_numbered = |number: u8| {
+            // 4096 samples at 44.1kHz, with zeroed CRCs and no subframes:
+            vec![0xff, 0xf8, 0x79, 0x18, number, 0x0f, 0xff, 0, 0, 0]
+        };
+        let mut frame = OffsetFrame::new(3);
+        frame
+            .process(Packet::new_from_slice(0, 8192, 4096, &frame_numbered(2)))
+            .unwrap();
+        let error = frame
+            .process(Packet::new_from_slice(0, 4096, 4096, &frame_numbered(1)))
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::CorruptFrame { track: 3, ts: 4096, ref reason }
+                if reason.contains("before the track's first frame")),
+            "{error:?}"
+        );
+    }
+
+    #[test]
+    fn uncommon_block_sizes() {
+        // A variable block size frame with zeroed CRCs and no
+        // subframes, whose block size (minus one) follows the sample
+        // number in 8 or 16 bits:
+        let frame_at = |sample: u64, block_size: &[u8]| {
+            let mut frame = vec![0xff, 0xf9, 0x59 + 0x10 * block_size.len() as u8, 0x18];
+            frame.extend(utf8_encode_be_u64(sample).unwrap());
+            frame.extend(block_size);
+            frame.extend([0, 0, 0]);
+            frame
+        };
+        let number = |frame: &[u8]| {
+            utf8_decode_be_u64(&mut BufReader::new(&frame[4..]))
+                .unwrap()
+                .0
+        };
+
+        let mut frame = OffsetFrame::new(1);
+        let first = frame
+            .process(Packet::new_from_slice(
+                0,
+                10000,
+                64,
+                &frame_at(10000, &[63]),
+            ))
+            .unwrap();
+        let second = frame
+            .process(Packet::new_from_slice(
+                0,
+                10064,
+                4096,
+                &frame_at(10064, &[0x0f, 0xff]),
+            ))
+            .unwrap();
+        assert_eq!(number(&first), 0);
+        assert_eq!(number(&second), 64);
+        assert_eq!(frame.samples_processed(), 64 + 4096);
+        assert_eq!(frame.next_number(), 64 + 4096);
+
+        // Frames appended to those continue after their samples:
+        let mut appended = OffsetFrame::numbered_from(2, frame.next_number());
+        let third = appended
+            .process(Packet::new_from_slice(0, 0, 64, &frame_at(0, &[63])))
+            .unwrap();
+        assert_eq!(number(&third), 64 + 4096);
+    }
+
     proptest! {
         #[test]
         fn test_encoding(input in 0..(2u64.pow(35))) {
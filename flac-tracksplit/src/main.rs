@@ -1,35 +1,602 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::Mutex,
+};
 
-use anyhow::Context;
+use anyhow::{Context, anyhow, bail};
 use bytesize::ByteSize;
-use clap::Parser;
-use flac_tracksplit::split_one_file;
+use clap::{Parser, Subcommand, ValueEnum};
+use flac_tracksplit::{
+    CollisionStrategy, Error, ExistingPolicy, Interrupt, JoinOptions, Naming, NumberTags,
+    PathLimits, PicturePolicy, PlanFile, PlaylistFormat, PregapPolicy, SidecarTags, SplitOptions,
+    SplitPlan, SplitReport, TagRules, TrackTagConvention, colliding_plans, plan_split,
+    resolve_collisions,
+};
 use rayon::prelude::*;
-use tracing::error;
+use serde::Serialize;
+use tracing::{error, warn};
 use tracing_subscriber::EnvFilter;
 use tracing_subscriber::prelude::*;
 
 #[derive(Debug, Parser)]
-#[command(author, version, about, long_about=None)]
+#[command(author, version, about, long_about=None, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Pathnames of .flac files (with embedded CUE sheets) to split into tracks.
     paths: Vec<PathBuf>,
 
     /// Output directory into which to sort resulting per-track FLAC files.
-    /// Tracks will be named according to this template:
+    /// Tracks will be named according to this template (see `--naming`):
     ///
     /// OUTPUT_DIR/<Album Artist>/<Release year> - <Album name>/[<discno>-]<Trackno>.<Track title>.flac
     #[arg(long, default_value = "./")]
     output_dir: PathBuf,
 
+    /// How to lay out the tracks below the output directory.
+    ///
+    /// * `artist-album`: a directory per album artist, holding a
+    ///   directory per album.
+    ///
+    /// * `album`: a directory per album, directly in the output
+    ///   directory.
+    #[arg(long, default_value_t = Naming::ArtistAlbum)]
+    naming: Naming,
+
+    /// What to do with the audio before the first track's INDEX 01
+    /// (such as a hidden track), if the CUE sheet has an INDEX 00
+    /// before it.
+    ///
+    /// * `split`: write it to a track 00 of its own.
+    ///
+    /// * `prepend`: keep it at the start of the first track.
+    ///
+    /// * `drop`: leave it out.
+    #[arg(long, default_value_t = PregapPolicy::Split)]
+    pregap: PregapPolicy,
+
     /// Number of 0-byte padding to add to the end of the metadata
     /// block. More padding allows larger additions to metadata
     /// without having to rewrite the whole file.
     #[arg(long, default_value = "2kB")]
     metadata_padding: ByteSize,
+
+    /// Maximum length in bytes of each directory and file name
+    /// created. Longer artist, album and track title names are
+    /// truncated to fit.
+    #[arg(long, default_value_t = PathLimits::default().max_component_bytes)]
+    max_component_bytes: usize,
+
+    /// Maximum length in bytes of each output track's entire path,
+    /// including the output directory. Track titles (then album and
+    /// artist names) are truncated to fit.
+    #[arg(long)]
+    max_path_bytes: Option<usize>,
+
+    /// What to do when two tracks (from the same or different input
+    /// files) would be written to the same output path, or two input
+    /// files' playlists, CUE sheets or (differing) extracted pictures.
+    /// All output paths are checked before any file gets written.
+    ///
+    /// * `error`: refuse to split anything.
+    ///
+    /// * `suffix`: add a numeric suffix like ` (2)` to the later tracks'
+    ///   (or album files') names.
+    ///
+    /// * `disambiguate`: include the disc number in the colliding
+    ///   discs' track and album file names, and if that isn't enough,
+    ///   their catalog number in the album directory name.
+    #[arg(long, default_value_t = CollisionStrategy::Error)]
+    on_collision: CollisionStrategy,
+
+    /// What to do with output files that already exist.
+    ///
+    /// * `overwrite`: replace them.
+    ///
+    /// * `skip`: leave them alone.
+    ///
+    /// * `fail`: refuse to split anything if any output file exists.
+    ///
+    /// * `if-changed`: only replace files whose tags, pictures,
+    ///   padding or audio frames differ from what would be written, leaving
+    ///   unchanged files (and their modification times) alone. Files
+    ///   are compared byte for byte (not by checksum), so an unchanged
+    ///   file is read in full.
+    #[arg(long, default_value_t = ExistingPolicy::Overwrite)]
+    existing: ExistingPolicy,
+
+    /// If splitting an input file fails (or is interrupted), also
+    /// remove the tracks that were already written from it, so no
+    /// incomplete album remains in the output directory. Tracks that
+    /// replaced files which existed before are kept.
+    ///
+    /// Incomplete track files are always removed.
+    #[arg(long)]
+    remove_partial: bool,
+
+    /// Write a playlist of each input file's tracks into its album
+    /// directory, in this format (`m3u8` or `xspf`). Can be given
+    /// more than once. Playlists refer to tracks by relative paths,
+    /// and are named after the album directory (plus the disc number,
+    /// for multi-disc albums).
+    #[arg(long = "playlist", value_name = "FORMAT")]
+    playlists: Vec<PlaylistFormat>,
+
+    /// Write a CUE sheet describing each input file's tracks into its
+    /// album directory, with one FILE per track and the original
+    /// index points (including pregaps), MCN and ISRCs, e.g. for
+    /// burning a copy of the disc.
+    #[arg(long)]
+    cue_sheet: bool,
+
+    /// Which of each input file's pictures to embed into its tracks.
+    ///
+    /// * `all`: every picture.
+    ///
+    /// * `front-cover`: only front covers.
+    ///
+    /// * `none`: no pictures.
+    ///
+    /// * `max-size=SIZE`: only pictures of at most SIZE (e.g. `500kB`),
+    ///   such as a cover but not a booklet scan.
+    #[arg(long, value_name = "POLICY", default_value_t = PicturePolicy::All)]
+    pictures: PicturePolicy,
+
+    /// Write each input file's pictures into its album directory, once
+    /// per album rather than in every track: front and back covers as
+    /// cover.jpg and back.jpg (or .png etc., after their format), the
+    /// others named after their picture type, like leaflet.jpg,
+    /// leaflet-2.jpg. Combine with `--pictures` to not embed them as
+    /// well.
+    #[arg(long)]
+    extract_pictures: bool,
+
+    /// Use a cover.jpg or folder.jpg (or .png) file next to an input
+    /// file that has no pictures as its front cover, embedding it into
+    /// the tracks (according to `--pictures`) and extracting it with
+    /// `--extract-pictures`.
+    #[arg(long)]
+    pick_up_cover: bool,
+
+    /// How to fill in each track's TRACKNUMBER, TRACKTOTAL and
+    /// TOTALTRACKS tags from the CUE sheet.
+    ///
+    /// * `keep`: leave the tags as the disc image has them.
+    ///
+    /// * `fill`: add the tags where they are missing, and split
+    ///   `3/12`-style TRACKNUMBER and DISCNUMBER values into number and
+    ///   total (TRACKTOTAL/TOTALTRACKS, DISCTOTAL/TOTALDISCS). Values
+    ///   that disagree with the CUE sheet are kept, with a warning.
+    ///
+    /// * `override`: like `fill`, but replace values that disagree
+    ///   with the CUE sheet.
+    #[arg(long, default_value_t = NumberTags::Fill)]
+    number_tags: NumberTags,
+
+    /// Uppercase tag keys, and write TOTALDISCS, TOTALTRACKS, YEAR and
+    /// ALBUM ARTIST as DISCTOTAL, TRACKTOTAL, DATE and ALBUMARTIST
+    /// (dropping them if those are tagged as well).
+    #[arg(long)]
+    normalize_tags: bool,
+
+    /// The conventions in which the disc image's tags for single tracks
+    /// are recognised (comma-separated, track numbers may be
+    /// zero-padded):
+    ///
+    /// * `suffix`: TITLE[3]
+    ///
+    /// * `disc-suffix`: TITLE[1.3], track 3 of disc 1 (only tags of the
+    ///   image's DISCNUMBER apply, if it has one)
+    ///
+    /// * `cue-track`: CUE_TRACK03_TITLE
+    ///
+    /// * `track-prefix`: TRACK03TITLE
+    #[arg(
+        long,
+        value_name = "CONVENTIONS",
+        value_delimiter = ',',
+        default_value = "suffix,disc-suffix,cue-track,track-prefix"
+    )]
+    track_tags: Vec<TrackTagConvention>,
+
+    /// Use tags from a sidecar file next to each input file, e.g.
+    /// `album.tags` for `album.flac` (in `metaflac --export-tags-to`
+    /// format, with per-track tags like TITLE[3]), `album.tags.csv` (a
+    /// table whose first column, TRACK, holds the track number, or
+    /// nothing for album tags, and whose header row holds the keys) or
+    /// `album.tags.json` (`{"album": {KEY: value}, "tracks": {"3": {KEY:
+    /// value}}}`, values can be arrays):
+    ///
+    /// * `ignore`: don't look for sidecar files.
+    ///
+    /// * `overlay`: keys in the sidecar file replace the input file's
+    ///   values of the same keys, for the album or the track.
+    ///
+    /// * `replace`: use the sidecar file's tags instead of the input
+    ///   file's.
+    #[arg(long, value_name = "MODE", default_value_t = SidecarTags::Ignore)]
+    sidecar_tags: SidecarTags,
+
+    /// A TOML file of rules that drop, rename, set, add and rewrite
+    /// each track's tags, after the number tags are filled in and the
+    /// keys normalized. Each `[[rule]]` can be limited to `tracks` by
+    /// number, and to tracks `when` tags have given values or
+    /// `when_matches` regular expressions, e.g.:
+    ///
+    /// [[rule]]
+    /// drop = ["COMMENT", "ENCODER"]
+    /// rename = { ORGANIZATION = "LABEL" }
+    ///
+    /// [[rule]]
+    /// when = { ALBUMARTIST = "Various Artists" }
+    /// set = { COMPILATION = "1" }
+    ///
+    /// [[rule]]
+    /// rewrite = [{ key = "TITLE", pattern = " \\(Remastered\\)$", replacement = "" }]
+    #[arg(long, value_name = "FILE")]
+    tag_rules: Option<PathBuf>,
+
+    /// Record in each track's tags where it was split from:
+    /// SOURCE_FILE, SOURCE_MD5 (of the disc image's audio),
+    /// SOURCE_START_SAMPLE and SOURCE_END_SAMPLE (the track's
+    /// boundaries in the disc image), SOURCE_CUE_TRACK and
+    /// SOURCE_BOUNDARY (how frames at the boundaries were assigned).
+    #[arg(long)]
+    provenance: bool,
+
+    /// Carry on splitting the other input files when one fails, and
+    /// print a summary of what happened to each file at the end.
+    ///
+    /// The exit status is 0 if all files were split, 3 if some were
+    /// skipped (e.g. for lacking a CUE sheet), and 1 if any failed.
+    #[arg(long)]
+    keep_going: bool,
+
+    /// Print the tracks that would be written, with their output
+    /// paths and tags, without writing anything.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Write the planned tracks to this file as JSON, without
+    /// splitting anything. The file can be edited (e.g. to fix titles,
+    /// output paths or track boundaries) and then split with
+    /// `--apply-plan`.
+    #[arg(long, value_name = "FILE")]
+    write_plan: Option<PathBuf>,
+
+    /// Split exactly the tracks planned in this file (as written by
+    /// `--write-plan`), instead of planning the tracks of PATHS.
+    ///
+    /// The tracks get the boundaries, output paths, tags and pictures
+    /// listed in the plan file, so the options that pick those can't
+    /// be given with it.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "paths",
+            "naming",
+            "pregap",
+            "pictures",
+            "number_tags",
+            "normalize_tags",
+            "track_tags",
+            "sidecar_tags",
+            "tag_rules",
+        ]
+    )]
+    apply_plan: Option<PathBuf>,
+
+    /// Write a report of what happened to each input file to this
+    /// file: its stream and CUE sheet, and each track's output path,
+    /// sample range, duration, size, tags and boundary error (the
+    /// samples of neighbouring tracks included by splitting at frame
+    /// boundaries).
+    ///
+    /// Without `--keep-going`, input files that weren't attempted
+    /// because another one failed are left out.
+    #[arg(long, value_name = "FILE")]
+    report: Option<PathBuf>,
+
+    /// Format of the `--report` file.
+    ///
+    /// * `json`: a single JSON document, written at the end of the run.
+    ///
+    /// * `ndjson`: one JSON object per input file and line, written as
+    ///   soon as the file is done.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Json, requires = "report")]
+    report_format: ReportFormat,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Join tracks split from a disc image back into a single FLAC
+    /// file with an embedded CUE sheet, losslessly: Splitting the
+    /// joined image gives back the same track files, byte for byte.
+    Join(JoinArgs),
+}
+
+#[derive(Debug, clap::Args)]
+struct JoinArgs {
+    /// Pathnames of the track .flac files to join, in order. Tracks
+    /// are numbered by their TRACKNUMBER tag or file name (as split
+    /// by this tool), and a track 00 becomes the next track's pregap.
+    #[arg(required = true)]
+    tracks: Vec<PathBuf>,
+
+    /// The FLAC file to write the joined disc image to.
+    #[arg(long, short)]
+    output: PathBuf,
+
+    /// Number of 0-byte padding to add to the end of the joined
+    /// image's metadata block.
+    #[arg(long, default_value = "2kB")]
+    metadata_padding: ByteSize,
+
+    /// What to do if the output file already exists (see the
+    /// `--existing` option for splitting).
+    #[arg(long, default_value_t = ExistingPolicy::Overwrite)]
+    existing: ExistingPolicy,
+
+    /// Write the joined image without checking that splitting it
+    /// gives back the tracks byte for byte, e.g. if they were edited
+    /// since they were split.
+    #[arg(long)]
+    no_verify: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ReportFormat {
+    Json,
+    Ndjson,
+}
+
+/// The version of the `--report` format.
+const REPORT_VERSION: u32 = 1;
+
+/// What happened to an input file, in the `--report`.
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum InputStatus {
+    /// Planned with `--dry-run` or `--write-plan`.
+    Planned,
+    Split,
+    Skipped,
+    Failed,
 }
 
-fn main() -> anyhow::Result<()> {
+/// An input file's entry in the `--report`.
+#[derive(Debug, Serialize)]
+struct InputReport {
+    input_path: PathBuf,
+    status: InputStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    split: Option<SplitReport>,
+}
+
+/// The `--report` in JSON format.
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    version: u32,
+    inputs: &'a [InputReport],
+}
+
+/// Writes the `--report`, if one was asked for.
+struct Reporter {
+    format: ReportFormat,
+    state: Option<Mutex<ReporterState>>,
+}
+
+struct ReporterState {
+    out: BufWriter<File>,
+    inputs: Vec<InputReport>,
+    /// The first error writing an NDJSON line, reported by
+    /// [Reporter::finish].
+    error: Option<anyhow::Error>,
+}
+
+impl Reporter {
+    fn new(path: Option<&Path>, format: ReportFormat) -> anyhow::Result<Self> {
+        let state = match path {
+            None => None,
+            Some(path) => {
+                let f = File::create(path).with_context(|| format!("creating {:?}", path))?;
+                Some(Mutex::new(ReporterState {
+                    out: BufWriter::new(f),
+                    inputs: vec![],
+                    error: None,
+                }))
+            }
+        };
+        Ok(Reporter { format, state })
+    }
+
+    /// Record what happened to the input file at `input_path`.
+    fn record(
+        &self,
+        input_path: &Path,
+        status: InputStatus,
+        error: Option<String>,
+        split: Option<SplitReport>,
+    ) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let input = InputReport {
+            input_path: input_path.to_path_buf(),
+            status,
+            error,
+            split,
+        };
+        let mut state = state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        match self.format {
+            ReportFormat::Json => state.inputs.push(input),
+            ReportFormat::Ndjson => {
+                let state = &mut *state;
+                let written = serde_json::to_writer(&mut state.out, &input)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|()| Ok(writeln!(state.out)?))
+                    .and_then(|()| Ok(state.out.flush()?));
+                if let Err(err) = written {
+                    state.error.get_or_insert(err);
+                }
+            }
+        }
+    }
+
+    /// Record the outcome of executing `plan`, see [execute_plan].
+    fn record_split(&self, plan: &SplitPlan, result: &anyhow::Result<usize>, split: SplitReport) {
+        let (status, error) = match result {
+            Ok(_) => (InputStatus::Split, None),
+            Err(err) => (InputStatus::Failed, Some(format!("{:#}", err))),
+        };
+        self.record(plan.input_path(), status, error, Some(split));
+    }
+
+    /// Complete the report.
+    fn finish(self) -> anyhow::Result<()> {
+        let Some(state) = self.state else {
+            return Ok(());
+        };
+        let mut state = state
+            .into_inner()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(err) = state.error {
+            return Err(err);
+        }
+        if self.format == ReportFormat::Json {
+            let report = Report {
+                version: REPORT_VERSION,
+                inputs: &state.inputs,
+            };
+            serde_json::to_writer_pretty(&mut state.out, &report)?;
+            writeln!(state.out)?;
+        }
+        state.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Split the tracks planned in `plan`, recording the outcome in the
+/// `reporter`, and return the number of track files written.
+fn execute_plan(plan: &SplitPlan, reporter: &Reporter) -> anyhow::Result<usize> {
+    let mut split = plan.report();
+    let result = plan
+        .execute_with_report(&mut split)
+        .map(|written| written.len())
+        .with_context(|| format!("splitting {:?}", plan.input_path()));
+    reporter.record_split(plan, &result, split);
+    result
+}
+
+/// Why input files without an embedded CUE sheet are skipped.
+const NO_CUE_SHEET: &str = "no embedded CUE sheet";
+
+/// Plan splitting the file at `path`, returning `None` (after logging
+/// a warning) if it has no embedded CUE sheet.
+fn plan(path: &Path, options: &SplitOptions) -> anyhow::Result<Option<SplitPlan>> {
+    match plan_split(path, options) {
+        Ok(plan) => Ok(Some(plan)),
+        Err(Error::NoCueSheet) => {
+            warn!(
+                ?path,
+                action = "skipping",
+                remedy = "Use `metaflac --import-cuesheet-from` to add the sheet and make the file splittable.",
+                "No embedded CUE sheet found."
+            );
+            Ok(None)
+        }
+        Err(err) => Err(err).with_context(|| format!("planning to split {:?}", path)),
+    }
+}
+
+/// Each input file's plan, or `None` if it has no embedded CUE sheet.
+type PlannedInputs = Vec<(PathBuf, anyhow::Result<Option<SplitPlan>>)>;
+
+/// Plan splitting every input file, or load the plans given with
+/// `--apply-plan`.
+fn plan_inputs(args: &Args, options: &SplitOptions) -> anyhow::Result<PlannedInputs> {
+    if let Some(plan_path) = &args.apply_plan {
+        let f = File::open(plan_path).with_context(|| format!("opening {:?}", plan_path))?;
+        let plan_file: PlanFile = serde_json::from_reader(BufReader::new(f))
+            .with_context(|| format!("reading plan file {:?}", plan_path))?;
+        plan_file
+            .check_version()
+            .with_context(|| format!("reading plan file {:?}", plan_path))?;
+        return Ok(plan_file
+            .plans
+            .into_par_iter()
+            .map(|entry| {
+                let path = entry.input_path.clone();
+                let plan = entry
+                    .into_plan(options)
+                    .with_context(|| format!("loading the plan for {:?}", path));
+                (path, plan.map(Some))
+            })
+            .collect());
+    }
+    Ok(args
+        .paths
+        .par_iter()
+        .map(|path| (path.clone(), plan(path, options)))
+        .collect())
+}
+
+/// Print the tracks that `plans` would write.
+fn print_plans(plans: &[SplitPlan]) {
+    for plan in plans {
+        println!("{}", plan.input_path().display());
+        for planned in plan.tracks() {
+            let track = &planned.track;
+            println!(
+                "  {} (samples {}..{})",
+                planned.output_path.display(),
+                track.start_ts,
+                track.end_ts
+            );
+            for tag in &track.tags {
+                println!("      {}={}", tag.key, tag.value);
+            }
+            for picture in &track.pictures {
+                println!(
+                    "      picture: {} ({} bytes)",
+                    picture.mime_type().unwrap_or_default(),
+                    picture.data().map_or(0, <[u8]>::len)
+                );
+            }
+        }
+    }
+}
+
+fn read_tag_rules(path: Option<&Path>) -> anyhow::Result<TagRules> {
+    let Some(path) = path else {
+        return Ok(TagRules::default());
+    };
+    let rules = fs::read_to_string(path).with_context(|| format!("reading {:?}", path))?;
+    rules
+        .parse()
+        .with_context(|| format!("reading tag rules {:?}", path))
+}
+
+fn write_plan_file(path: &Path, plans: &[SplitPlan]) -> anyhow::Result<()> {
+    let plan_file = PlanFile::from_plans(plans)?;
+    let mut f = BufWriter::new(File::create(path).with_context(|| format!("creating {:?}", path))?);
+    serde_json::to_writer_pretty(&mut f, &plan_file)?;
+    writeln!(f)?;
+    f.flush()?;
+    Ok(())
+}
+
+fn main() -> anyhow::Result<ExitCode> {
     // Setup logging:
     let indicatif_layer = tracing_indicatif::IndicatifLayer::new();
     let filter = EnvFilter::builder()
@@ -46,26 +613,350 @@ fn main() -> anyhow::Result<()> {
         .with(indicatif_layer)
         .init();
 
+    // On the first SIGINT/SIGTERM, let splits in progress clean up
+    // after themselves; if that takes too long, a second one exits
+    // immediately.
+    let interrupt = Interrupt::new();
+    let handler_interrupt = interrupt.clone();
+    ctrlc::set_handler(move || {
+        if handler_interrupt.is_interrupted() {
+            std::process::exit(EXIT_INTERRUPTED.into());
+        }
+        warn!("Interrupted, cleaning up...");
+        handler_interrupt.interrupt();
+    })
+    .context("setting up signal handler")?;
+
     let args = Args::parse();
-    let base_path = args.output_dir.as_path();
-    let metadata_padding: u32 = args
-        .metadata_padding
+    if let Some(Command::Join(join_args)) = &args.command {
+        return join(join_args, &interrupt);
+    }
+    let metadata_padding = padding_bytes(args.metadata_padding)?;
+    let options = SplitOptions::new(&args.output_dir)
+        .metadata_padding(metadata_padding)
+        .path_limits(PathLimits {
+            max_component_bytes: args.max_component_bytes,
+            max_path_bytes: args.max_path_bytes,
+        })
+        .naming(args.naming)
+        .pregap(args.pregap)
+        .on_collision(args.on_collision)
+        .existing(args.existing)
+        .remove_partial(args.remove_partial)
+        .playlists(args.playlists.iter().copied())
+        .cue_sheet(args.cue_sheet)
+        .pictures(args.pictures)
+        .extract_pictures(args.extract_pictures)
+        .pick_up_cover(args.pick_up_cover)
+        .provenance(args.provenance)
+        .number_tags(args.number_tags)
+        .normalize_tags(args.normalize_tags)
+        .track_tag_conventions(args.track_tags.iter().copied())
+        .sidecar_tags(args.sidecar_tags)
+        .tag_rules(read_tag_rules(args.tag_rules.as_deref())?)
+        .interrupt(interrupt.clone());
+    let reporter = Reporter::new(args.report.as_deref(), args.report_format)?;
+    let result = run(&args, &options, &reporter, &interrupt);
+    let reported = reporter
+        .finish()
+        .with_context(|| format!("writing report {:?}", args.report));
+    match (result, reported) {
+        (Err(err), Err(report_err)) => {
+            error!(error = %format!("{:#}", report_err));
+            Err(err)
+        }
+        (result, reported) => {
+            reported?;
+            result
+        }
+    }
+}
+
+fn padding_bytes(metadata_padding: ByteSize) -> anyhow::Result<u32> {
+    metadata_padding
         .as_u64()
         .try_into()
-        .context("--metadata-padding should fit into a 32-bit unsigned int")?;
-    if let Err(err) = args
-        .paths
-        .into_par_iter()
-        .panic_fuse()
-        .try_for_each(|path| {
-            split_one_file(&path, base_path, metadata_padding)
-                .map(|_| ())
-                .with_context(|| format!("splitting {:?}", path))
+        .context("--metadata-padding should fit into a 32-bit unsigned int")
+}
+
+/// Join the tracks given to the `join` command, until `interrupt`
+/// stops it.
+fn join(args: &JoinArgs, interrupt: &Interrupt) -> anyhow::Result<ExitCode> {
+    let options = JoinOptions::new()
+        .metadata_padding(padding_bytes(args.metadata_padding)?)
+        .existing(args.existing)
+        .verify(!args.no_verify)
+        .interrupt(interrupt.clone());
+    match options.join(&args.tracks, &args.output) {
+        Ok(_) => Ok(ExitCode::SUCCESS),
+        Err(Error::Interrupted) => Ok(ExitCode::from(EXIT_INTERRUPTED)),
+        Err(err) => Err(err).with_context(|| format!("joining tracks into {:?}", args.output)),
+    }
+}
+
+/// Split (or plan) the input files according to `args`, recording
+/// what happens to each in the `reporter`. `interrupt` is the handle
+/// that the `options` were given.
+fn run(
+    args: &Args,
+    options: &SplitOptions,
+    reporter: &Reporter,
+    interrupt: &Interrupt,
+) -> anyhow::Result<ExitCode> {
+    let planned = plan_inputs(args, options)?;
+    for (path, plan) in &planned {
+        match plan {
+            Err(err) => {
+                reporter.record(path, InputStatus::Failed, Some(format!("{:#}", err)), None)
+            }
+            Ok(None) => reporter.record(
+                path,
+                InputStatus::Skipped,
+                Some(NO_CUE_SHEET.to_string()),
+                None,
+            ),
+            Ok(Some(_)) => {}
+        }
+    }
+    if args.dry_run || args.write_plan.is_some() {
+        let mut plans = planned
+            .into_iter()
+            .filter_map(|(_, plan)| plan.transpose())
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        resolve_collisions(&mut plans, args.on_collision)?;
+        for plan in &plans {
+            reporter.record(
+                plan.input_path(),
+                InputStatus::Planned,
+                None,
+                Some(plan.report()),
+            );
+        }
+        if args.dry_run {
+            print_plans(&plans);
+        }
+        if let Some(path) = &args.write_plan {
+            write_plan_file(path, &plans)
+                .with_context(|| format!("writing plan file {:?}", path))?;
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+    if args.keep_going {
+        let outcomes = split_keeping_going(args, planned, reporter)?;
+        print_summary(&outcomes);
+        if interrupt.is_interrupted() {
+            return Ok(ExitCode::from(EXIT_INTERRUPTED));
+        }
+        let exit_code = if outcomes
+            .iter()
+            .any(|(_, o)| matches!(o, Outcome::Failed(_)))
+        {
+            EXIT_SOME_FAILED
+        } else if outcomes
+            .iter()
+            .any(|(_, o)| matches!(o, Outcome::Skipped(_)))
+        {
+            EXIT_SOME_SKIPPED
+        } else {
+            0
+        };
+        return Ok(ExitCode::from(exit_code));
+    }
+
+    let result = planned
+        .into_iter()
+        .filter_map(|(_, plan)| plan.transpose())
+        .collect::<anyhow::Result<Vec<_>>>()
+        .and_then(|mut plans| {
+            let checked = check_plans(args, &mut plans);
+            if let Err(err) = &checked {
+                for plan in &plans {
+                    let error = Some(format!("{:#}", err));
+                    reporter.record(
+                        plan.input_path(),
+                        InputStatus::Failed,
+                        error,
+                        Some(plan.report()),
+                    );
+                }
+            }
+            checked.map(|()| plans)
         })
-    {
+        .and_then(|plans| {
+            plans
+                .into_par_iter()
+                .panic_fuse()
+                .try_for_each(|plan| execute_plan(&plan, reporter).map(|_| ()))
+        });
+    if let Err(err) = result {
         error!(error = %err);
+        if interrupt.is_interrupted() {
+            return Ok(ExitCode::from(EXIT_INTERRUPTED));
+        }
         Err(err)
     } else {
-        Ok(())
+        Ok(ExitCode::SUCCESS)
+    }
+}
+
+/// Resolve collisions between the output paths of `plans`, and check
+/// that none of them exist if `--existing=fail`.
+fn check_plans(args: &Args, plans: &mut [SplitPlan]) -> anyhow::Result<()> {
+    resolve_collisions(plans, args.on_collision)?;
+    if args.existing == ExistingPolicy::Fail {
+        let existing: Vec<PathBuf> = plans
+            .iter()
+            .flat_map(|plan| plan.existing_outputs())
+            .collect();
+        if !existing.is_empty() {
+            bail!(
+                "{} output file(s) already exist: {:?}",
+                existing.len(),
+                existing
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Exit code if splitting was interrupted by SIGINT/SIGTERM.
+const EXIT_INTERRUPTED: u8 = 130;
+
+/// Exit code with `--keep-going` if any input file failed to split.
+const EXIT_SOME_FAILED: u8 = 1;
+
+/// Exit code with `--keep-going` if no input failed, but some were
+/// skipped.
+const EXIT_SOME_SKIPPED: u8 = 3;
+
+/// What happened to an input file with `--keep-going`.
+enum Outcome {
+    /// Split into tracks; holds the number of track files written.
+    Split(usize),
+    /// Not split, for the given reason.
+    Skipped(&'static str),
+    Failed(anyhow::Error),
+}
+
+/// Split every input file, carrying on past the ones that fail, and
+/// return what happened to each.
+///
+/// Output paths are still checked for collisions across all files
+/// before any of them get written; files whose tracks collide (and
+/// can't be resolved) fail.
+fn split_keeping_going(
+    args: &Args,
+    planned: PlannedInputs,
+    reporter: &Reporter,
+) -> anyhow::Result<Vec<(PathBuf, Outcome)>> {
+    let mut outcomes = vec![];
+    let mut plans = vec![];
+    for (path, plan) in planned {
+        match plan {
+            Err(err) => {
+                error!(error = %format!("{:#}", err));
+                outcomes.push((path, Outcome::Failed(err)));
+            }
+            Ok(None) => outcomes.push((path, Outcome::Skipped(NO_CUE_SHEET))),
+            Ok(Some(plan)) => plans.push(plan),
+        }
+    }
+    if let Err(err) = resolve_collisions(&mut plans, args.on_collision) {
+        // Fail only the files involved in collisions, and carry on
+        // with the others:
+        error!(error = %err);
+        let colliding = colliding_plans(&plans);
+        let (colliding, rest): (Vec<_>, Vec<_>) = plans
+            .into_iter()
+            .enumerate()
+            .partition(|(i, _)| colliding.contains(i));
+        for (_, plan) in colliding {
+            let err = anyhow!("output paths collide with other tracks (see log)");
+            let error = Some(format!("{:#}", err));
+            reporter.record(
+                plan.input_path(),
+                InputStatus::Failed,
+                error,
+                Some(plan.report()),
+            );
+            outcomes.push((plan.input_path().to_path_buf(), Outcome::Failed(err)));
+        }
+        plans = rest.into_iter().map(|(_, plan)| plan).collect();
+    }
+    if args.existing == ExistingPolicy::Fail {
+        let (blocked, rest): (Vec<_>, Vec<_>) = plans
+            .into_iter()
+            .partition(|plan| plan.existing_outputs().next().is_some());
+        for plan in blocked {
+            let existing: Vec<PathBuf> = plan.existing_outputs().collect();
+            let err = anyhow!(
+                "{} output file(s) already exist: {:?}",
+                existing.len(),
+                existing
+            );
+            error!(input_path = ?plan.input_path(), error = %err);
+            let error = Some(format!("{:#}", err));
+            reporter.record(
+                plan.input_path(),
+                InputStatus::Failed,
+                error,
+                Some(plan.report()),
+            );
+            outcomes.push((plan.input_path().to_path_buf(), Outcome::Failed(err)));
+        }
+        plans = rest;
+    }
+    let executed: Vec<(PathBuf, Outcome)> = plans
+        .into_par_iter()
+        .map(|plan| {
+            let path = plan.input_path().to_path_buf();
+            let outcome = match execute_plan(&plan, reporter) {
+                Ok(written) => Outcome::Split(written),
+                Err(err) => {
+                    error!(error = %format!("{:#}", err));
+                    Outcome::Failed(err)
+                }
+            };
+            (path, outcome)
+        })
+        .collect();
+    outcomes.extend(executed);
+
+    // Report in the order the files were given:
+    let order: HashMap<&PathBuf, usize> = args
+        .paths
+        .iter()
+        .enumerate()
+        .map(|(i, path)| (path, i))
+        .collect();
+    outcomes.sort_by_key(|(path, _)| order.get(path).copied());
+    Ok(outcomes)
+}
+
+fn print_summary(outcomes: &[(PathBuf, Outcome)]) {
+    println!("{:<8} {:>6}  FILE", "STATUS", "TRACKS");
+    for (path, outcome) in outcomes {
+        match outcome {
+            Outcome::Split(tracks) => println!("{:<8} {:>6}  {}", "ok", tracks, path.display()),
+            Outcome::Skipped(reason) => {
+                println!(
+                    "{:<8} {:>6}  {} ({})",
+                    "skipped",
+                    "-",
+                    path.display(),
+                    reason
+                )
+            }
+            Outcome::Failed(err) => {
+                println!("{:<8} {:>6}  {}: {:#}", "failed", "-", path.display(), err)
+            }
+        }
     }
+    let count = |f: fn(&Outcome) -> bool| outcomes.iter().filter(|(_, o)| f(o)).count();
+    println!(
+        "{} split, {} skipped, {} failed",
+        count(|o| matches!(o, Outcome::Split(_))),
+        count(|o| matches!(o, Outcome::Skipped(_))),
+        count(|o| matches!(o, Outcome::Failed(_))),
+    );
 }
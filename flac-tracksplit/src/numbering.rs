@@ -0,0 +1,307 @@
+use std::{fmt, str::FromStr};
+use symphonia_core::meta::{StandardTagKey, Tag, Value};
+use tracing::warn;
+
+use crate::{Error, Track};
+
+/// The keys of the tags holding the number of tracks on a disc.
+const TRACK_TOTAL_KEYS: [&str; 2] = ["TRACKTOTAL", "TOTALTRACKS"];
+
+/// The keys of the tags holding the number of discs in a release.
+const DISC_TOTAL_KEYS: [&str; 2] = ["DISCTOTAL", "TOTALDISCS"];
+
+/// How the `TRACKNUMBER`, `TRACKTOTAL`/`TOTALTRACKS` and
+/// `DISCNUMBER`/`DISCTOTAL`/`TOTALDISCS` tags of split tracks are
+/// filled in from the CUE sheet.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NumberTags {
+    /// Leave the tags as the disc image has them.
+    Keep,
+
+    /// Add the track number and total where they aren't tagged, split
+    /// `3/12`-style values into number and total, and tag totals under
+    /// both of their names. Tagged values that disagree with the CUE
+    /// sheet are kept, with a warning.
+    #[default]
+    Fill,
+
+    /// Like [NumberTags::Fill], but replace tagged values that
+    /// disagree with the CUE sheet.
+    Override,
+}
+
+impl FromStr for NumberTags {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(NumberTags::Keep),
+            "fill" => Ok(NumberTags::Fill),
+            "override" => Ok(NumberTags::Override),
+            _ => Err(Error::UnknownOption {
+                option: "number tags policy",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for NumberTags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            NumberTags::Keep => "keep",
+            NumberTags::Fill => "fill",
+            NumberTags::Override => "override",
+        })
+    }
+}
+
+fn position(tags: &[Tag], key: &str) -> Option<usize> {
+    tags.iter()
+        .position(|tag| tag.key.eq_ignore_ascii_case(key))
+}
+
+fn string_tag(std_key: Option<StandardTagKey>, key: &str, value: impl ToString) -> Tag {
+    Tag::new(std_key, key, Value::String(value.to_string()))
+}
+
+/// Turn a `<number>/<total>` or zero-padded value of the `key` tag
+/// into a plain number, moving the total into `total_keys` tags
+/// unless those are tagged already. Values that aren't numbers are
+/// left alone.
+fn normalize(tags: &mut Vec<Tag>, key: &str, total_keys: &[&str]) {
+    let Some(i) = position(tags, key) else {
+        return;
+    };
+    let value = tags[i].value.to_string();
+    let (number, total) = match value.split_once('/') {
+        Some((number, total)) => (number.trim(), total.trim().parse::<u32>().ok()),
+        None => (value.trim(), None),
+    };
+    let Ok(number) = number.parse::<u32>() else {
+        return;
+    };
+    tags[i] = string_tag(tags[i].std_key, &tags[i].key.clone(), number);
+    if let Some(total) = total
+        && !total_keys.iter().any(|key| position(tags, key).is_some())
+    {
+        for key in total_keys {
+            tags.push(string_tag(None, key, total));
+        }
+    }
+}
+
+/// Add whichever of the `keys`, which hold the same value under
+/// different names, isn't tagged, if any of them is.
+fn fill_aliases(tags: &mut Vec<Tag>, keys: &[&str]) {
+    let Some(value) = keys
+        .iter()
+        .find_map(|key| position(tags, key))
+        .map(|i| tags[i].value.to_string())
+    else {
+        return;
+    };
+    for key in keys {
+        if position(tags, key).is_none() {
+            tags.push(string_tag(None, key, &value));
+        }
+    }
+}
+
+impl NumberTags {
+    /// Fill in the number tags of `track`, which is one of
+    /// `total_tracks` tracks on its disc (not counting a pregap
+    /// track 0).
+    pub(crate) fn apply(self, track: &mut Track, total_tracks: u32) {
+        if self == NumberTags::Keep {
+            return;
+        }
+        let number = track.number;
+        normalize(&mut track.tags, "DISCNUMBER", &DISC_TOTAL_KEYS);
+        normalize(&mut track.tags, "TRACKNUMBER", &TRACK_TOTAL_KEYS);
+        fill_aliases(&mut track.tags, &DISC_TOTAL_KEYS);
+        fill_aliases(&mut track.tags, &TRACK_TOTAL_KEYS);
+        self.set(
+            number,
+            &mut track.tags,
+            &mut track.warnings,
+            &[("TRACKNUMBER", Some(StandardTagKey::TrackNumber))],
+            number,
+        );
+        self.set(
+            number,
+            &mut track.tags,
+            &mut track.warnings,
+            &[
+                (TRACK_TOTAL_KEYS[0], Some(StandardTagKey::TrackTotal)),
+                (TRACK_TOTAL_KEYS[1], None),
+            ],
+            total_tracks,
+        );
+    }
+
+    /// Set the tags with the given `keys` (and standard keys) of track
+    /// number `track` to `value` if none of them are tagged, or else
+    /// check (and with [NumberTags::Override], fix) the tagged ones,
+    /// adding to `warnings` if they disagree.
+    fn set(
+        self,
+        track: u32,
+        tags: &mut Vec<Tag>,
+        warnings: &mut Vec<String>,
+        keys: &[(&str, Option<StandardTagKey>)],
+        value: u32,
+    ) {
+        let tagged: Vec<usize> = keys
+            .iter()
+            .filter_map(|(key, _)| position(tags, key))
+            .collect();
+        if tagged.is_empty() {
+            for (key, std_key) in keys {
+                tags.push(string_tag(*std_key, key, value));
+            }
+            return;
+        }
+        for i in tagged {
+            let tag = &tags[i];
+            let tagged_value = tag.value.to_string();
+            if tagged_value.trim().parse::<u32>() == Ok(value) {
+                continue;
+            }
+            if self == NumberTags::Override {
+                warn!(
+                    track,
+                    key = tag.key,
+                    tagged = tagged_value,
+                    cue = value,
+                    "Replacing tag that disagrees with the CUE sheet."
+                );
+                warnings.push(format!(
+                    "replaced {}={} with the CUE sheet's {}",
+                    tag.key, tagged_value, value
+                ));
+                tags[i] = string_tag(tag.std_key, &tag.key.clone(), value);
+            } else {
+                warn!(
+                    track,
+                    key = tag.key,
+                    tagged = tagged_value,
+                    cue = value,
+                    remedy = "Use `--number-tags=override` to replace it.",
+                    "Tag disagrees with the CUE sheet."
+                );
+                warnings.push(format!(
+                    "{}={} disagrees with the CUE sheet's {}",
+                    tag.key, tagged_value, value
+                ));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metaflac::block::StreamInfo;
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<Tag> {
+        tags.iter()
+            .map(|(key, value)| string_tag(None, key, value))
+            .collect()
+    }
+
+    fn pairs(tags: &[Tag]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|tag| (tag.key.clone(), tag.value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn normalizing() {
+        let mut normalized = tags(&[("DISCNUMBER", "01/2"), ("TITLE", "One")]);
+        normalize(&mut normalized, "DISCNUMBER", &DISC_TOTAL_KEYS);
+        assert_eq!(
+            pairs(&normalized),
+            pairs(&tags(&[
+                ("DISCNUMBER", "1"),
+                ("TITLE", "One"),
+                ("DISCTOTAL", "2"),
+                ("TOTALDISCS", "2")
+            ]))
+        );
+
+        let mut normalized = tags(&[("tracknumber", "3/12"), ("TOTALTRACKS", "11")]);
+        normalize(&mut normalized, "TRACKNUMBER", &TRACK_TOTAL_KEYS);
+        assert_eq!(
+            pairs(&normalized),
+            pairs(&tags(&[("tracknumber", "3"), ("TOTALTRACKS", "11")]))
+        );
+
+        let mut normalized = tags(&[("DISCNUMBER", "A")]);
+        normalize(&mut normalized, "DISCNUMBER", &DISC_TOTAL_KEYS);
+        assert_eq!(pairs(&normalized), pairs(&tags(&[("DISCNUMBER", "A")])));
+    }
+
+    #[test]
+    fn fill_and_override() {
+        let numbered = |policy: NumberTags, tagged: &[(&str, &str)]| {
+            let mut track =
+                Track::new(&StreamInfo::new(), 3, 0, 100, tags(tagged), vec![]).unwrap();
+            policy.apply(&mut track, 12);
+            (pairs(&track.tags), track.warnings)
+        };
+        let tagged = [
+            ("TRACKNUMBER", "5"),
+            ("TRACKTOTAL", "9"),
+            ("DISCNUMBER", "2/3"),
+        ];
+
+        // Missing tags are filled in:
+        let (filled, warnings) = numbered(NumberTags::Fill, &[("DISCNUMBER", "2")]);
+        assert_eq!(
+            filled,
+            pairs(&tags(&[
+                ("DISCNUMBER", "2"),
+                ("TRACKNUMBER", "3"),
+                ("TRACKTOTAL", "12"),
+                ("TOTALTRACKS", "12"),
+            ]))
+        );
+        assert!(warnings.is_empty(), "{warnings:?}");
+
+        // Tagged values are kept, with a warning if they disagree:
+        let (filled, warnings) = numbered(NumberTags::Fill, &tagged);
+        assert_eq!(
+            filled,
+            pairs(&tags(&[
+                ("TRACKNUMBER", "5"),
+                ("TRACKTOTAL", "9"),
+                ("DISCNUMBER", "2"),
+                ("DISCTOTAL", "3"),
+                ("TOTALDISCS", "3"),
+                ("TOTALTRACKS", "9"),
+            ]))
+        );
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+
+        // ...or replaced by the CUE sheet's, except for the disc
+        // number it doesn't know:
+        let (overridden, warnings) = numbered(NumberTags::Override, &tagged);
+        assert_eq!(
+            overridden,
+            pairs(&tags(&[
+                ("TRACKNUMBER", "3"),
+                ("TRACKTOTAL", "12"),
+                ("DISCNUMBER", "2"),
+                ("DISCTOTAL", "3"),
+                ("TOTALDISCS", "3"),
+                ("TOTALTRACKS", "12"),
+            ]))
+        );
+        assert_eq!(warnings.len(), 3, "{warnings:?}");
+
+        let (kept, warnings) = numbered(NumberTags::Keep, &tagged);
+        assert_eq!(kept, pairs(&tags(&tagged)));
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    fmt::Debug,
+    path::{Path, PathBuf},
+};
+use tracing::{instrument, warn};
+
+use crate::{
+    CollisionStrategy, Error, ExistingPolicy, Interrupt, Naming, NumberTags, PathLimits,
+    PicturePolicy, PlaylistFormat, PregapPolicy, Result, SidecarTags, SplitPlan, TagRules,
+    TrackTagConvention, plan_split, resolve_collisions,
+};
+
+/// The metadata padding that [SplitOptions] default to, in bytes.
+pub const DEFAULT_METADATA_PADDING: u32 = 2000;
+
+/// Settings for splitting disc images, built up from defaults.
+///
+/// ```no_run
+/// use flac_tracksplit::{ExistingPolicy, SplitOptions};
+///
+/// let written = SplitOptions::new("/music")
+///     .metadata_padding(8192)
+///     .existing(ExistingPolicy::Skip)
+///     .split("/archive/album.flac")?;
+/// # Ok::<(), flac_tracksplit::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct SplitOptions {
+    pub(crate) base_path: PathBuf,
+    pub(crate) metadata_padding: u32,
+    pub(crate) path_limits: PathLimits,
+    pub(crate) naming: Naming,
+    pub(crate) pregap: PregapPolicy,
+    pub(crate) on_collision: CollisionStrategy,
+    pub(crate) existing: ExistingPolicy,
+    pub(crate) remove_partial: bool,
+    pub(crate) playlists: Vec<PlaylistFormat>,
+    pub(crate) cue_sheet: bool,
+    pub(crate) provenance: bool,
+    pub(crate) number_tags: NumberTags,
+    pub(crate) normalize_tags: bool,
+    pub(crate) track_tag_conventions: Vec<TrackTagConvention>,
+    pub(crate) tag_rules: TagRules,
+    pub(crate) sidecar_tags: SidecarTags,
+    pub(crate) pictures: PicturePolicy,
+    pub(crate) extract_pictures: bool,
+    pub(crate) pick_up_cover: bool,
+    pub(crate) interrupt: Interrupt,
+}
+
+impl Default for SplitOptions {
+    fn default() -> Self {
+        Self::new("./")
+    }
+}
+
+impl SplitOptions {
+    /// Options for splitting tracks into directories below
+    /// `base_path`, with default settings otherwise.
+    pub fn new(base_path: impl AsRef<Path>) -> Self {
+        Self {
+            base_path: base_path.as_ref().to_path_buf(),
+            metadata_padding: DEFAULT_METADATA_PADDING,
+            path_limits: PathLimits::default(),
+            naming: Naming::default(),
+            pregap: PregapPolicy::default(),
+            on_collision: CollisionStrategy::default(),
+            existing: ExistingPolicy::default(),
+            remove_partial: false,
+            playlists: vec![],
+            cue_sheet: false,
+            provenance: false,
+            number_tags: NumberTags::default(),
+            normalize_tags: false,
+            track_tag_conventions: TrackTagConvention::ALL.to_vec(),
+            tag_rules: TagRules::default(),
+            sidecar_tags: SidecarTags::default(),
+            pictures: PicturePolicy::default(),
+            extract_pictures: false,
+            pick_up_cover: false,
+            interrupt: Interrupt::new(),
+        }
+    }
+
+    /// The number of 0-bytes of padding to add after each track's
+    /// metadata blocks.
+    pub fn metadata_padding(mut self, metadata_padding: u32) -> Self {
+        self.metadata_padding = metadata_padding;
+        self
+    }
+
+    /// The limits that output paths get truncated to.
+    pub fn path_limits(mut self, path_limits: PathLimits) -> Self {
+        self.path_limits = path_limits;
+        self
+    }
+
+    /// How the tracks are laid out below the output directory.
+    pub fn naming(mut self, naming: Naming) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// What to do with the audio before the first track's INDEX 01
+    /// when planning a split.
+    pub fn pregap(mut self, pregap: PregapPolicy) -> Self {
+        self.pregap = pregap;
+        self
+    }
+
+    /// How [SplitOptions::split] resolves tracks that would be
+    /// written to the same output path.
+    pub fn on_collision(mut self, on_collision: CollisionStrategy) -> Self {
+        self.on_collision = on_collision;
+        self
+    }
+
+    /// What happens to output files that already exist.
+    pub fn existing(mut self, existing: ExistingPolicy) -> Self {
+        self.existing = existing;
+        self
+    }
+
+    /// Whether to remove the tracks already written from a disc image
+    /// that fails to split. Tracks that replaced files which existed
+    /// before are kept.
+    pub fn remove_partial(mut self, remove_partial: bool) -> Self {
+        self.remove_partial = remove_partial;
+        self
+    }
+
+    /// The formats of the playlists to write for each disc image, into
+    /// the album directory, once its tracks were split. Playlists
+    /// refer to tracks by relative paths.
+    pub fn playlists(mut self, formats: impl IntoIterator<Item = PlaylistFormat>) -> Self {
+        self.playlists = formats.into_iter().collect();
+        self
+    }
+
+    /// Whether to write a CUE sheet for each disc image into the album
+    /// directory, once its tracks were split. It describes the tracks
+    /// as one FILE each, with the index points, MCN and ISRCs of the
+    /// disc image's CUE sheet.
+    pub fn cue_sheet(mut self, cue_sheet: bool) -> Self {
+        self.cue_sheet = cue_sheet;
+        self
+    }
+
+    /// Whether to record in each track's tags where it was split
+    /// from: the disc image's file name and audio MD5, the track's
+    /// sample range and CUE sheet track, and how its boundaries were
+    /// cut, see [Provenance](crate::Provenance).
+    pub fn provenance(mut self, provenance: bool) -> Self {
+        self.provenance = provenance;
+        self
+    }
+
+    /// How the tracks' number tags get filled in from the CUE sheet
+    /// when planning a split; tracks loaded from a
+    /// [PlanFile](crate::PlanFile) keep their tags as planned.
+    pub fn number_tags(mut self, number_tags: NumberTags) -> Self {
+        self.number_tags = number_tags;
+        self
+    }
+
+    /// Whether to normalize the tracks' tag keys when planning a split:
+    /// uppercase them, and replace the aliases `TOTALDISCS`,
+    /// `TOTALTRACKS`, `YEAR` and `ALBUM ARTIST` by `DISCTOTAL`,
+    /// `TRACKTOTAL`, `DATE` and `ALBUMARTIST`. Values of an alias
+    /// whose common key is tagged as well are dropped.
+    ///
+    /// Tags are looked up ignoring case either way.
+    pub fn normalize_tags(mut self, normalize_tags: bool) -> Self {
+        self.normalize_tags = normalize_tags;
+        self
+    }
+
+    /// The conventions in which the disc image's tags for single
+    /// tracks are recognised, all of them by default. Keys ending in
+    /// `]` never apply to all tracks, whether they are recognised or
+    /// not.
+    pub fn track_tag_conventions(
+        mut self,
+        conventions: impl IntoIterator<Item = TrackTagConvention>,
+    ) -> Self {
+        self.track_tag_conventions = conventions.into_iter().collect();
+        self
+    }
+
+    /// What to do with a sidecar tag file next to each disc image,
+    /// whose tags are applied to the image's before they are divided
+    /// up into tracks.
+    pub fn sidecar_tags(mut self, sidecar_tags: SidecarTags) -> Self {
+        self.sidecar_tags = sidecar_tags;
+        self
+    }
+
+    /// Rules that rewrite each track's tags when planning a split,
+    /// after its number tags are filled in and its keys normalized.
+    /// The output paths follow the rewritten tags.
+    pub fn tag_rules(mut self, tag_rules: TagRules) -> Self {
+        self.tag_rules = tag_rules;
+        self
+    }
+
+    /// Which of the disc image's pictures get embedded into each
+    /// track when planning a split.
+    pub fn pictures(mut self, pictures: PicturePolicy) -> Self {
+        self.pictures = pictures;
+        self
+    }
+
+    /// Whether to write each of the disc image's pictures into the
+    /// album directory once its tracks were split, whether they are
+    /// embedded or not: front and back covers as `cover.jpg` and
+    /// `back.jpg` (or the extension of their format), the others
+    /// named after their picture type, like `leaflet.jpg`,
+    /// `leaflet-2.jpg` and so on.
+    pub fn extract_pictures(mut self, extract_pictures: bool) -> Self {
+        self.extract_pictures = extract_pictures;
+        self
+    }
+
+    /// Whether to use a `cover.jpg` or `folder.jpg` (or `.png`) file
+    /// next to a disc image that has no pictures as its front cover,
+    /// as if it was embedded.
+    pub fn pick_up_cover(mut self, pick_up_cover: bool) -> Self {
+        self.pick_up_cover = pick_up_cover;
+        self
+    }
+
+    /// The handle that stops splits with these options, see
+    /// [Interrupt]. Each [SplitOptions::new] gets a handle of its own.
+    pub fn interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    /// The directory below which tracks get written.
+    pub fn base_path(&self) -> &Path {
+        &self.base_path
+    }
+
+    /// Plan splitting the FLAC file at `input_path`, see [plan_split].
+    pub fn plan<P: AsRef<Path> + Debug>(&self, input_path: P) -> Result<SplitPlan> {
+        plan_split(input_path, self)
+    }
+
+    /// Split a FLAC file with an embedded CUE sheet into tracks,
+    /// returning the paths of the files written.
+    ///
+    /// Files without an embedded CUE sheet are skipped with a
+    /// warning.
+    #[instrument(skip(self), err)]
+    pub fn split<P: AsRef<Path> + Debug>(&self, input_path: P) -> Result<Vec<PathBuf>> {
+        let mut plan = match self.plan(input_path) {
+            Err(Error::NoCueSheet) => {
+                warn!(
+                    action = "skipping",
+                    remedy = "Use `metaflac --import-cuesheet-from` to add the sheet and make the file splittable.",
+                    "No embedded CUE sheet found."
+                );
+                return Ok(vec![]);
+            }
+            plan => plan?,
+        };
+        resolve_collisions(std::slice::from_mut(&mut plan), self.on_collision)?;
+        plan.execute()
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tracing::warn;
+
+use crate::{Error, Result};
+
+/// The file name extension of every output track.
+pub(crate) const EXTENSION: &str = ".flac";
+
+/// Limits on the length of output pathnames, in bytes.
+///
+/// Most filesystems limit a single path component to 255 bytes, and
+/// some (or some network shares) additionally limit the length of the
+/// entire path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathLimits {
+    /// Maximum length of a single directory or file name.
+    pub max_component_bytes: usize,
+
+    /// Maximum length of the entire output path, including the base
+    /// directory. `None` means the total length is not limited.
+    pub max_path_bytes: Option<usize>,
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self {
+            max_component_bytes: 255,
+            max_path_bytes: None,
+        }
+    }
+}
+
+/// How output tracks are laid out below the output directory. The
+/// file names are the same either way, e.g. `02-07.Title.flac` for
+/// track 7 of disc 2 of a multi-disc album.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Naming {
+    /// A directory per (album) artist, holding a directory per album:
+    /// `Artist/2001 - Album/07.Title.flac`.
+    #[default]
+    ArtistAlbum,
+
+    /// A directory per album, directly in the output directory:
+    /// `2001 - Album/07.Title.flac`.
+    Album,
+}
+
+impl FromStr for Naming {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "artist-album" => Ok(Naming::ArtistAlbum),
+            "album" => Ok(Naming::Album),
+            _ => Err(Error::UnknownOption {
+                option: "naming",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for Naming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Naming::ArtistAlbum => "artist-album",
+            Naming::Album => "album",
+        })
+    }
+}
+
+/// The unlimited parts making up a track's output path, relative to
+/// the output directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PathParts {
+    /// Which of the parts below make up the path.
+    pub(crate) naming: Naming,
+    pub(crate) artist: String,
+    pub(crate) album: String,
+    /// Appended to the album directory name to tell apart releases
+    /// that would otherwise share a directory, e.g. ` [CAT-123]`.
+    pub(crate) album_suffix: Option<String>,
+    /// The disc number, if known.
+    pub(crate) disc: Option<String>,
+    /// Whether to prefix the track number with the disc number.
+    pub(crate) show_disc: bool,
+    pub(crate) number: String,
+    pub(crate) title: Option<String>,
+    /// Appended to the file name (before the extension) to tell apart
+    /// tracks that would otherwise share a name, e.g. ` (2)`.
+    pub(crate) suffix: Option<String>,
+}
+
+impl PathParts {
+    /// The file name prefix identifying the track, e.g. `02-07`.
+    fn prefix(&self) -> String {
+        match (&self.disc, self.show_disc) {
+            (Some(disc), true) => format!("{}-{}", disc, self.number),
+            _ => self.number.clone(),
+        }
+    }
+
+    fn suffix(&self) -> &str {
+        self.suffix.as_deref().unwrap_or("")
+    }
+
+    fn album_suffix(&self) -> &str {
+        self.album_suffix.as_deref().unwrap_or("")
+    }
+
+    /// Whether the path starts with an artist directory.
+    fn has_artist(&self) -> bool {
+        self.naming == Naming::ArtistAlbum
+    }
+
+    fn file_name(&self) -> String {
+        match &self.title {
+            Some(title) if !title.is_empty() => {
+                format!("{}.{}{}{}", self.prefix(), title, self.suffix(), EXTENSION)
+            }
+            _ => format!("{}{}{}", self.prefix(), self.suffix(), EXTENSION),
+        }
+    }
+
+    /// Returns the relative pathname without applying any limits.
+    pub(crate) fn to_path(&self) -> PathBuf {
+        let artist = self.has_artist().then(|| self.artist.clone());
+        artist
+            .into_iter()
+            .chain([
+                format!("{}{}", self.album, self.album_suffix()),
+                self.file_name(),
+            ])
+            .collect()
+    }
+
+    /// Returns the relative pathname, truncating the artist, album
+    /// and title parts such that each component and the overall path
+    /// (joined to `base_path`) fit into `limits`.
+    ///
+    /// The track number prefix, any disambiguating suffixes and the
+    /// file extension are never truncated. Parts that are truncated
+    /// are logged, and added to `warnings`.
+    pub(crate) fn to_limited_path(
+        &self,
+        base_path: &Path,
+        limits: &PathLimits,
+        warnings: &mut Vec<String>,
+    ) -> Result<PathBuf> {
+        let fixed_name_len =
+            self.prefix().len() + ".".len() + self.suffix().len() + EXTENSION.len();
+        let fixed_album_len = self.album_suffix().len() + 1;
+        if fixed_name_len > limits.max_component_bytes
+            || fixed_album_len > limits.max_component_bytes
+        {
+            return Err(Error::PathTooLong {
+                path: self.to_path(),
+                max_bytes: limits.max_component_bytes,
+            });
+        }
+        // Without an artist directory, the artist takes up no room:
+        let mut artist = match self.has_artist() {
+            true => truncate_str(&self.artist, limits.max_component_bytes),
+            false => "",
+        };
+        let mut album = truncate_str(
+            &self.album,
+            limits.max_component_bytes - self.album_suffix().len(),
+        );
+        let mut title = self
+            .title
+            .as_deref()
+            .map(|title| truncate_str(title, limits.max_component_bytes - fixed_name_len));
+
+        if let Some(max_path_bytes) = limits.max_path_bytes {
+            // The base path, plus one separator for each component:
+            let fixed_len = base_path.as_os_str().len()
+                + if self.has_artist() { 3 } else { 2 }
+                + self.album_suffix().len()
+                + self.prefix().len()
+                + self.suffix().len()
+                + EXTENSION.len();
+            let current_len = |artist: &str, album: &str, title: Option<&str>| {
+                fixed_len
+                    + artist.len()
+                    + album.len()
+                    + title.map(|t| t.len() + ".".len()).unwrap_or(0)
+            };
+            let mut excess = current_len(artist, album, title).saturating_sub(max_path_bytes);
+            // Shorten the title first, then the album, then the artist name:
+            if let Some(t) = title
+                && excess > 0
+            {
+                let shortened = truncate_str(t, t.len().saturating_sub(excess));
+                title = (!shortened.is_empty()).then_some(shortened);
+                excess = current_len(artist, album, title).saturating_sub(max_path_bytes);
+            }
+            if excess > 0 {
+                album = truncate_str(
+                    album,
+                    album
+                        .len()
+                        .saturating_sub(excess)
+                        .max(first_char_len(album)),
+                );
+                excess = current_len(artist, album, title).saturating_sub(max_path_bytes);
+            }
+            if excess > 0 {
+                artist = truncate_str(
+                    artist,
+                    artist
+                        .len()
+                        .saturating_sub(excess)
+                        .max(first_char_len(artist)),
+                );
+                excess = current_len(artist, album, title).saturating_sub(max_path_bytes);
+            }
+            if excess > 0 {
+                return Err(Error::PathTooLong {
+                    path: base_path.join(self.to_path()),
+                    max_bytes: max_path_bytes,
+                });
+            }
+        }
+
+        if self.has_artist() {
+            warn_if_truncated("artist", &self.artist, artist, warnings);
+        }
+        warn_if_truncated("album", &self.album, album, warnings);
+        if let Some(original) = &self.title {
+            warn_if_truncated("title", original, title.unwrap_or(""), warnings);
+        }
+        let limited = PathParts {
+            artist: artist.to_string(),
+            album: album.to_string(),
+            title: title.map(str::to_string),
+            ..self.clone()
+        };
+        Ok(limited.to_path())
+    }
+}
+
+fn first_char_len(s: &str) -> usize {
+    s.chars().next().map(char::len_utf8).unwrap_or(0)
+}
+
+fn warn_if_truncated(part: &str, original: &str, truncated: &str, warnings: &mut Vec<String>) {
+    if original.len() != truncated.len() {
+        warn!(
+            part,
+            original,
+            truncated,
+            original_bytes = original.len(),
+            truncated_bytes = truncated.len(),
+            "Truncated path component to fit filesystem limits."
+        );
+        warnings.push(format!(
+            "truncated the {} {:?} to {:?} to fit filesystem limits",
+            part, original, truncated
+        ));
+    }
+}
+
+/// Returns the longest prefix of `s` that is at most `max_bytes`
+/// long and ends on a UTF-8 character boundary.
+pub(crate) fn truncate_str(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::{prop_assert, proptest};
+
+    fn parts(title: &str) -> PathParts {
+        PathParts {
+            naming: Naming::default(),
+            artist: "Some Artist".to_string(),
+            album: "2001 - Some Album".to_string(),
+            album_suffix: None,
+            disc: None,
+            show_disc: false,
+            number: "03".to_string(),
+            title: Some(title.to_string()),
+            suffix: None,
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn truncation_respects_char_boundaries(s in "\\PC*", max in 0usize..64) {
+            let truncated = truncate_str(&s, max);
+            prop_assert!(truncated.len() <= max);
+            prop_assert!(s.starts_with(truncated));
+        }
+
+        #[test]
+        fn limited_paths_fit(title in "[a-zA-Z0-9 äöü€𝄞]{0,200}", max_component in 10usize..300, max_path in 40usize..400) {
+            let limits = PathLimits { max_component_bytes: max_component, max_path_bytes: Some(max_path) };
+            let base = Path::new("/music");
+            let path = parts(&title).to_limited_path(base, &limits, &mut vec![]).expect("should fit");
+            prop_assert!(base.join(&path).as_os_str().len() <= max_path);
+            for component in path.iter() {
+                prop_assert!(component.len() <= max_component);
+            }
+            let file_name = path.file_name().unwrap().to_str().unwrap();
+            prop_assert!(file_name.starts_with("03"));
+            prop_assert!(file_name.ends_with(EXTENSION));
+        }
+    }
+
+    #[test]
+    fn album_naming() {
+        let parts = PathParts {
+            naming: Naming::Album,
+            ..parts("Title")
+        };
+        assert_eq!(
+            parts.to_path(),
+            Path::new("2001 - Some Album/03.Title.flac")
+        );
+
+        // Only the album directory and file name count:
+        let limits = PathLimits {
+            max_component_bytes: 255,
+            max_path_bytes: Some("/music/2001 - Some Album/03.Ti.flac".len()),
+        };
+        let mut warnings = vec![];
+        assert_eq!(
+            parts
+                .to_limited_path(Path::new("/music"), &limits, &mut warnings)
+                .unwrap(),
+            Path::new("2001 - Some Album/03.Ti.flac")
+        );
+        assert_eq!(warnings.len(), 1, "{warnings:?}");
+    }
+
+    #[test]
+    fn short_paths_are_untouched() {
+        let parts = parts("Title");
+        let mut warnings = vec![];
+        assert_eq!(
+            parts
+                .to_limited_path(Path::new("/music"), &PathLimits::default(), &mut warnings)
+                .unwrap(),
+            parts.to_path()
+        );
+        assert_eq!(warnings, Vec::<String>::new());
+    }
+}
@@ -0,0 +1,198 @@
+use metaflac::Block;
+use std::{borrow::Cow, fmt};
+
+use crate::blocks::PICTURE_BLOCK_TYPE;
+
+/// The picture type of front covers.
+pub(crate) const FRONT_COVER: u32 = 3;
+
+/// The picture type of back covers.
+pub(crate) const BACK_COVER: u32 = 4;
+
+/// The names of the picture types that the FLAC format defines, by
+/// number.
+const PICTURE_TYPE_NAMES: [&str; 21] = [
+    "Other",
+    "FileIcon",
+    "OtherIcon",
+    "FrontCover",
+    "BackCover",
+    "Leaflet",
+    "Media",
+    "LeadArtist",
+    "Artist",
+    "Conductor",
+    "Band",
+    "Composer",
+    "Lyricist",
+    "RecordingLocation",
+    "DuringRecording",
+    "DuringPerformance",
+    "ScreenCapture",
+    "BrightColoredFish",
+    "Illustration",
+    "BandLogo",
+    "PublisherLogo",
+];
+
+/// A [PICTURE](https://xiph.org/flac/format.html#metadata_block_picture)
+/// metadata block, kept as it is stored in the disc image so that it
+/// gets copied into tracks byte for byte: with its description,
+/// dimensions, and even a picture type that the FLAC format doesn't
+/// define.
+///
+/// Its fields can be inspected, as far as the block holds them.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PictureBlock {
+    contents: Vec<u8>,
+}
+
+impl fmt::Debug for PictureBlock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PictureBlock")
+            .field("picture_type", &self.picture_type())
+            .field("mime_type", &self.mime_type())
+            .field("description", &self.description())
+            .field("bytes", &self.data().map(<[u8]>::len))
+            .finish()
+    }
+}
+
+impl PictureBlock {
+    /// A picture block with the given `contents` (without the block
+    /// header), as stored in a FLAC file.
+    pub fn from_bytes(contents: Vec<u8>) -> Self {
+        Self { contents }
+    }
+
+    /// A picture block of `picture_type` holding `data` of
+    /// `mime_type`, with no description. Its `dimensions` are the
+    /// width, height and colour depth in bits per pixel, or 0 if they
+    /// are unknown.
+    pub(crate) fn new(
+        picture_type: u32,
+        mime_type: &str,
+        (width, height, depth): (u32, u32, u32),
+        data: &[u8],
+    ) -> Self {
+        let mut contents = vec![];
+        contents.extend(picture_type.to_be_bytes());
+        contents.extend((mime_type.len() as u32).to_be_bytes());
+        contents.extend(mime_type.as_bytes());
+        contents.extend(0u32.to_be_bytes());
+        for field in [width, height, depth, 0, data.len() as u32] {
+            contents.extend(field.to_be_bytes());
+        }
+        contents.extend(data);
+        Self { contents }
+    }
+
+    /// The block's contents, without the block header.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.contents
+    }
+
+    fn u32_at(&self, offset: usize) -> Option<u32> {
+        let bytes = self.contents.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    }
+
+    /// The length-prefixed bytes at `offset`, and the offset after
+    /// them.
+    fn bytes_at(&self, offset: usize) -> Option<(&[u8], usize)> {
+        let length = self.u32_at(offset)? as usize;
+        let start = offset + 4;
+        let bytes = self.contents.get(start..start.checked_add(length)?)?;
+        Some((bytes, start + length))
+    }
+
+    /// The offset of the picture's data length, after the MIME type,
+    /// description, dimensions, colour depth and number of colours.
+    fn data_offset(&self) -> Option<usize> {
+        let (_, after_mime_type) = self.bytes_at(4)?;
+        let (_, after_description) = self.bytes_at(after_mime_type)?;
+        Some(after_description + 16)
+    }
+
+    /// The picture type, e.g. 3 for the front cover.
+    pub fn picture_type(&self) -> Option<u32> {
+        self.u32_at(0)
+    }
+
+    /// The name of the picture type, e.g. `FrontCover`, if the FLAC
+    /// format defines it.
+    pub fn picture_type_name(&self) -> Option<&'static str> {
+        PICTURE_TYPE_NAMES
+            .get(usize::try_from(self.picture_type()?).ok()?)
+            .copied()
+    }
+
+    /// The picture's MIME type, e.g. `image/jpeg`.
+    pub fn mime_type(&self) -> Option<Cow<'_, str>> {
+        let (mime_type, _) = self.bytes_at(4)?;
+        Some(String::from_utf8_lossy(mime_type))
+    }
+
+    /// The picture's description.
+    pub fn description(&self) -> Option<Cow<'_, str>> {
+        let (_, after_mime_type) = self.bytes_at(4)?;
+        let (description, _) = self.bytes_at(after_mime_type)?;
+        Some(String::from_utf8_lossy(description))
+    }
+
+    /// The picture data, e.g. the contents of a JPEG file.
+    pub fn data(&self) -> Option<&[u8]> {
+        let (data, _) = self.bytes_at(self.data_offset()?)?;
+        Some(data)
+    }
+
+    /// The block, to be written to a FLAC file.
+    pub(crate) fn block(&self) -> Block {
+        Block::Unknown((PICTURE_BLOCK_TYPE, self.contents.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metaflac::block::{Picture, PictureType};
+
+    #[test]
+    fn fields() {
+        let picture = Picture {
+            picture_type: PictureType::CoverBack,
+            mime_type: "image/png".to_string(),
+            description: "Back".to_string(),
+            width: 1,
+            height: 2,
+            depth: 24,
+            num_colors: 0,
+            data: vec![1, 2, 3],
+        };
+        let block = PictureBlock::from_bytes(picture.to_bytes());
+        assert_eq!(block.picture_type(), Some(4));
+        assert_eq!(block.picture_type_name(), Some("BackCover"));
+        assert_eq!(block.mime_type().as_deref(), Some("image/png"));
+        assert_eq!(block.description().as_deref(), Some("Back"));
+        assert_eq!(block.data(), Some(&[1u8, 2, 3][..]));
+        assert_eq!(
+            PictureBlock::new(4, "image/png", (1, 2, 24), &[1, 2, 3]),
+            PictureBlock::from_bytes(
+                Picture {
+                    description: String::new(),
+                    ..picture.clone()
+                }
+                .to_bytes()
+            )
+        );
+
+        let mut contents = picture.to_bytes();
+        contents[3] = 99;
+        contents.truncate(contents.len() - 1);
+        let block = PictureBlock::from_bytes(contents);
+        assert_eq!(block.picture_type(), Some(99));
+        assert_eq!(block.picture_type_name(), None);
+        assert_eq!(block.description().as_deref(), Some("Back"));
+        assert_eq!(block.data(), None);
+    }
+}
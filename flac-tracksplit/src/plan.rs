@@ -0,0 +1,790 @@
+use metaflac::block::{CueSheet, StreamInfo};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Debug,
+    fs::{self, File},
+    io::{BufReader, ErrorKind},
+    path::{Path, PathBuf},
+};
+use symphonia_bundle_flac::FlacReader;
+use symphonia_core::{
+    formats::{Cue, CuePoint, FormatReader},
+    io::MediaSourceStream,
+};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{
+    END_OF_STREAM_TS, Error, ExistingPolicy, FilesystemSink, LEAD_OUT_TRACK_NUMBER, PathParts,
+    PictureBlock, PregapPolicy, Provenance, Result, SplitOptions, SplitReport, Track, TrackOutput,
+    TrackSink, TrackStatus,
+    blocks::{CUESHEET_BLOCK_TYPE, PICTURE_BLOCK_TYPE, RawBlock, read_raw_blocks},
+    cover::{picture_files, read_cover_file},
+    cue_sheet::render_cue_sheet,
+    paths::truncate_str,
+    report::CueSummary,
+    tag_keys::normalize_tags,
+    track_tags::{has_track_tags, select_track_tags},
+};
+
+/// Detect if the current cue track has any pregaps, and return their end/start timestamps, if so.
+fn maybe_pregap(cue: &Cue) -> Option<CuePoint> {
+    if !cue.index == 1 || cue.points.len() == 1 {
+        return None;
+    }
+    cue.points.last().cloned()
+}
+
+/// Check that the CUE sheet describes tracks that can be split out of
+/// a stream of `total_samples` samples: Tracks must start in order,
+/// each after the previous one, and within the stream; a lead-out, if
+/// present, must come last and may not lie past the stream's end.
+///
+/// If the stream's length is unknown (0), tracks can only be checked
+/// against each other.
+fn validate_cues(cues: &[Cue], total_samples: u64) -> Result<()> {
+    let end_ts = match total_samples {
+        0 => END_OF_STREAM_TS,
+        n => n,
+    };
+    let describe = |cue: &Cue| {
+        if cue.index == LEAD_OUT_TRACK_NUMBER {
+            "the lead-out".to_string()
+        } else {
+            format!("track {}", cue.index)
+        }
+    };
+    let invalid = |reason: String| Err(Error::InvalidCueSheet { reason });
+    let out_of_range = |cue: &Cue| {
+        Err(Error::CueOutOfRange {
+            track: cue.index,
+            start_ts: cue.start_ts,
+            total_samples,
+        })
+    };
+    for (i, cue) in cues.iter().enumerate() {
+        if cue.index == LEAD_OUT_TRACK_NUMBER {
+            if i == 0 {
+                return invalid(format!(
+                    "the lead-out at sample {} precedes all tracks",
+                    cue.start_ts
+                ));
+            }
+            if i != cues.len() - 1 {
+                return invalid(format!(
+                    "the lead-out at sample {} is followed by {}",
+                    cue.start_ts,
+                    describe(&cues[i + 1])
+                ));
+            }
+            if cue.start_ts > end_ts {
+                return out_of_range(cue);
+            }
+        } else if cue.start_ts >= end_ts {
+            return out_of_range(cue);
+        }
+        if let Some(previous) = i.checked_sub(1).map(|i| &cues[i])
+            && previous.start_ts >= cue.start_ts
+        {
+            return invalid(format!(
+                "{} starts at sample {}, not after {} at sample {}",
+                describe(cue),
+                cue.start_ts,
+                describe(previous),
+                previous.start_ts
+            ));
+        }
+    }
+    if let Some(first) = cues.first()
+        && let Some(pregap) = maybe_pregap(first)
+    {
+        let next_start = cues.get(1).map_or(end_ts, |cue| cue.start_ts);
+        if pregap.start_offset_ts >= next_start {
+            return invalid(format!(
+                "track {}'s pregap ends at sample {}, not before the next track or the stream's end at sample {}",
+                first.index, pregap.start_offset_ts, next_start
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Open a FLAC file for reading its frames, returning the reader,
+/// the stream's STREAMINFO and the file's length in bytes.
+pub(crate) fn open_flac(input_path: &Path) -> Result<(FlacReader, StreamInfo, u64)> {
+    let input_io = |source| Error::InputIo {
+        path: input_path.to_path_buf(),
+        source,
+    };
+    let not_flac = |reason: String| Error::NotFlac { reason };
+    let file = File::open(input_path).map_err(input_io)?;
+    let file_length = file.metadata().map_err(input_io)?.len();
+    let mss = MediaSourceStream::new(Box::new(file), Default::default());
+    let reader = match FlacReader::try_new(mss, &Default::default()) {
+        Ok(reader) => reader,
+        Err(symphonia_core::errors::Error::IoError(source))
+            if source.kind() != ErrorKind::UnexpectedEof =>
+        {
+            return Err(input_io(source));
+        }
+        Err(err) => return Err(not_flac(err.to_string())),
+    };
+    debug!("tracks: {:?}", reader.tracks());
+    let track = reader
+        .default_track()
+        .ok_or_else(|| not_flac("no default track".to_string()))?;
+    let data = match &track.codec_params.extra_data {
+        Some(it) => it,
+        _ => return Err(not_flac("unclear track codec params".to_string())),
+    };
+    let info = StreamInfo::from_bytes(data);
+    let time_base = track.codec_params.time_base;
+    if !matches!(time_base, Some(tb) if tb.numer == 1 && tb.denom == info.sample_rate) {
+        return Err(Error::InvalidTimeBase {
+            time_base,
+            sample_rate: info.sample_rate,
+        });
+    }
+    Ok((reader, info, file_length))
+}
+
+/// Read the STREAMINFO, pictures (see [source_pictures]) and CUE
+/// sheet tracks of the FLAC file at `input_path`.
+pub(crate) fn read_source(
+    input_path: &Path,
+    options: &SplitOptions,
+) -> Result<(StreamInfo, Vec<PictureBlock>, Vec<Cue>)> {
+    let (reader, info, _) = open_flac(input_path)?;
+    let cues = reader.cues().to_vec();
+    Ok((info, source_pictures(input_path, options)?, cues))
+}
+
+/// Read the metadata blocks of the FLAC file at `input_path`, as they
+/// are stored.
+pub(crate) fn read_blocks(input_path: &Path) -> Result<Vec<RawBlock>> {
+    let input_io = |source| Error::InputIo {
+        path: input_path.to_path_buf(),
+        source,
+    };
+    let mut file = BufReader::new(File::open(input_path).map_err(input_io)?);
+    read_raw_blocks(&mut file).map_err(|err| match err.kind() {
+        ErrorKind::InvalidData | ErrorKind::UnexpectedEof => Error::NotFlac {
+            reason: err.to_string(),
+        },
+        _ => input_io(err),
+    })
+}
+
+/// Read the PICTURE blocks of the FLAC file at `input_path`.
+pub(crate) fn read_pictures(input_path: &Path) -> Result<Vec<PictureBlock>> {
+    Ok(read_blocks(input_path)?
+        .into_iter()
+        .filter(|(block_type, _)| *block_type == PICTURE_BLOCK_TYPE)
+        .map(|(_, contents)| PictureBlock::from_bytes(contents))
+        .collect())
+}
+
+/// The pictures of the FLAC file at `input_path`, or the cover file
+/// next to it if it has none and [SplitOptions::pick_up_cover] is
+/// set.
+pub(crate) fn source_pictures(
+    input_path: &Path,
+    options: &SplitOptions,
+) -> Result<Vec<PictureBlock>> {
+    let pictures = read_pictures(input_path)?;
+    if !pictures.is_empty() || !options.pick_up_cover {
+        return Ok(pictures);
+    }
+    Ok(read_cover_file(input_path)?.into_iter().collect())
+}
+
+/// Read the CUESHEET block of the FLAC file at `input_path` in full;
+/// symphonia's [Cue]s lack the MCN and index point numbers.
+fn read_cue_sheet(input_path: &Path) -> Result<Option<CueSheet>> {
+    read_blocks(input_path)?
+        .iter()
+        .find(|(block_type, _)| *block_type == CUESHEET_BLOCK_TYPE)
+        .map(|(_, contents)| {
+            CueSheet::from_bytes(contents).map_err(|err| Error::InvalidCueSheet {
+                reason: err.description.to_string(),
+            })
+        })
+        .transpose()
+}
+
+/// A track that is planned to be split out of a disc image, and the
+/// path of the file it will be written to.
+#[derive(Debug, Clone)]
+pub struct PlannedTrack {
+    pub track: Track,
+    pub output_path: PathBuf,
+}
+
+/// The tracks that splitting a disc image will produce, worked out
+/// before any file gets written.
+#[derive(Debug, Clone)]
+pub struct SplitPlan {
+    input_path: PathBuf,
+    options: SplitOptions,
+    pub(crate) cue: CueSummary,
+    pub(crate) tracks: Vec<PlannedTrack>,
+    /// Things about the disc image worth a second look, noticed while
+    /// planning, for its [SplitReport].
+    pub(crate) warnings: Vec<String>,
+    /// What happened while working out each output path (such as
+    /// truncation), by path. Paths set by other means have no entry.
+    pub(crate) path_warnings: BTreeMap<PathBuf, Vec<String>>,
+    /// The pictures to write into the album directory, with
+    /// [SplitOptions::extract_pictures].
+    pub(crate) album_pictures: Vec<PictureBlock>,
+    /// Appended to the names of the album files (before the
+    /// extension) to tell them apart from another disc image's, e.g.
+    /// ` (Disc 2)`, replacing the disc number that playlists and CUE
+    /// sheets get by default.
+    pub(crate) album_file_suffix: Option<String>,
+}
+
+/// Plan splitting a FLAC file with an embedded CUE sheet into tracks
+/// according to `options`, without writing anything.
+///
+/// Fails with [Error::NoCueSheet] if the file has no embedded CUE
+/// sheet.
+#[instrument(skip(options), err)]
+pub fn plan_split<P: AsRef<Path> + Debug>(
+    input_path: P,
+    options: &SplitOptions,
+) -> Result<SplitPlan> {
+    let input_path = input_path.as_ref();
+    let (mut reader, info, _) = open_flac(input_path)?;
+    let cues = reader.cues().to_vec();
+    // since we're sure that the sample rate is an even denominator of
+    // symphonia's TimeBase, we can assume that the time stamps are in
+    // samples:
+    // If the stream doesn't know its length, the last track runs
+    // until the end of the stream:
+    let last_ts: u64 = match info.total_samples {
+        0 => END_OF_STREAM_TS,
+        n => n,
+    };
+
+    let mut tracks = vec![];
+    let provenance = |cue: &Cue| {
+        options
+            .provenance
+            .then(|| Provenance::new(input_path, &info, cue.index))
+    };
+    let mut cue_iter = cues.iter().peekable();
+    if cue_iter.peek().is_none() {
+        return Err(Error::NoCueSheet);
+    }
+    validate_cues(&cues, info.total_samples)?;
+
+    let metadata = reader.metadata();
+    let tags = match metadata.current() {
+        Some(current) => current.tags(),
+        None => &[][..],
+    };
+    let source_pictures = source_pictures(input_path, options)?;
+    let pictures = options.pictures.select(source_pictures.clone());
+
+    let (tags, conventions) =
+        options
+            .sidecar_tags
+            .apply(input_path, tags, &options.track_tag_conventions)?;
+    let (tags, conventions) = (&tags, &conventions);
+
+    // Detect the pregap, which is split into a track 0 that gets the
+    // tags of track 0 if there are any, and the first track's
+    // otherwise, kept with the first track, or left out:
+    let mut first_start_ts: Option<u64> = None;
+    if let Some(cue) = cue_iter.peek()
+        && let Some(pregap) = maybe_pregap(cue)
+    {
+        match options.pregap {
+            PregapPolicy::Split => {
+                let tagged = if has_track_tags(tags, conventions, 0) {
+                    0
+                } else {
+                    cue.index
+                };
+                let mut pregap_track = Track::new(
+                    &info,
+                    0,
+                    cue.start_ts,
+                    pregap.start_offset_ts,
+                    select_track_tags(tags, conventions, tagged),
+                    pictures.clone(),
+                )?;
+                pregap_track.provenance = provenance(cue);
+                debug!(number = pregap_track.number, output = ?pregap_track.pathname(), "Pregap");
+                tracks.push(pregap_track);
+                first_start_ts = Some(pregap.start_offset_ts);
+            }
+            PregapPolicy::Prepend => {}
+            PregapPolicy::Drop => first_start_ts = Some(pregap.start_offset_ts),
+        }
+    }
+
+    // Handle regular tracks:
+    while let Some(cue) = cue_iter.next() {
+        let next = cue_iter.peek();
+        let end_ts = match next {
+            None => last_ts, // no lead-out, fudge it.
+            Some(track) if track.index == LEAD_OUT_TRACK_NUMBER => {
+                // we have a lead-out, capture the whole in the last track.
+                let end_ts = track.start_ts;
+                cue_iter.next();
+                end_ts
+            }
+            Some(track) => track.start_ts,
+        };
+        let mut track = Track::new(
+            &info,
+            cue.index,
+            first_start_ts.take().unwrap_or(cue.start_ts),
+            end_ts,
+            select_track_tags(tags, conventions, cue.index),
+            pictures.clone(),
+        )?;
+        track.provenance = provenance(cue);
+        debug!(number = track.number, output = ?track.pathname(), "Track");
+        tracks.push(track);
+    }
+
+    let total_tracks = tracks.iter().filter(|track| track.number != 0).count();
+    for track in &mut tracks {
+        options
+            .number_tags
+            .apply(track, total_tracks.try_into().unwrap_or(u32::MAX));
+        if options.normalize_tags {
+            normalize_tags(track.number, &mut track.tags, &mut track.warnings);
+        }
+        options.tag_rules.apply(track);
+    }
+
+    let cue = CueSummary::new(&info, &cues);
+    let mut plan = SplitPlan::new(input_path, options, cue, tracks)?;
+    if options.extract_pictures {
+        plan.album_pictures = source_pictures;
+    }
+    Ok(plan)
+}
+
+impl SplitPlan {
+    pub(crate) fn new(
+        input_path: &Path,
+        options: &SplitOptions,
+        cue: CueSummary,
+        tracks: Vec<Track>,
+    ) -> Result<Self> {
+        let tracks = tracks
+            .into_iter()
+            .map(|track| PlannedTrack {
+                track,
+                output_path: PathBuf::new(),
+            })
+            .collect();
+        let mut plan = Self::with_tracks(input_path, options, cue, tracks);
+        plan.update_output_paths()?;
+        Ok(plan)
+    }
+
+    /// Create a plan whose tracks' output paths are already known.
+    pub(crate) fn with_tracks(
+        input_path: &Path,
+        options: &SplitOptions,
+        cue: CueSummary,
+        tracks: Vec<PlannedTrack>,
+    ) -> Self {
+        SplitPlan {
+            input_path: input_path.to_path_buf(),
+            options: options.clone(),
+            cue,
+            tracks,
+            warnings: vec![],
+            path_warnings: BTreeMap::new(),
+            album_pictures: vec![],
+            album_file_suffix: None,
+        }
+    }
+
+    /// The disc image that this plan splits.
+    pub fn input_path(&self) -> &Path {
+        &self.input_path
+    }
+
+    /// The options that this plan was made with, and will be
+    /// executed with.
+    pub fn options(&self) -> &SplitOptions {
+        &self.options
+    }
+
+    /// The tracks that will be written, in order.
+    pub fn tracks(&self) -> &[PlannedTrack] {
+        &self.tracks
+    }
+
+    /// The tracks that will be written, for filtering or adjusting
+    /// them before [executing](SplitPlan::execute) the plan.
+    ///
+    /// Tracks must stay in order, and may not overlap. Changing a
+    /// track's tags does not update its output path; use
+    /// [SplitPlan::update_output_paths] for that.
+    pub fn tracks_mut(&mut self) -> &mut Vec<PlannedTrack> {
+        &mut self.tracks
+    }
+
+    /// Recompute every track's output path from its tags, e.g. after
+    /// changing them.
+    pub fn update_output_paths(&mut self) -> Result<()> {
+        self.replan_paths(|_, _| {})
+    }
+
+    /// Compute a track's output path from its tags, after letting
+    /// `adjust` modify the parts that make up the path. Returns the
+    /// path along with any warnings about it.
+    pub(crate) fn path_for(
+        &self,
+        track: &Track,
+        adjust: impl FnOnce(&mut PathParts),
+    ) -> Result<(PathBuf, Vec<String>)> {
+        let mut parts = track.path_parts();
+        parts.naming = self.options.naming;
+        adjust(&mut parts);
+        let base_path = &self.options.base_path;
+        let mut warnings = vec![];
+        let relative =
+            parts.to_limited_path(base_path, &self.options.path_limits, &mut warnings)?;
+        Ok((base_path.join(relative), warnings))
+    }
+
+    /// Set the output path of the track at index `t`, as computed by
+    /// [SplitPlan::path_for].
+    pub(crate) fn set_output_path(&mut self, t: usize, (path, warnings): (PathBuf, Vec<String>)) {
+        self.path_warnings.insert(path.clone(), warnings);
+        self.tracks[t].output_path = path;
+    }
+
+    /// Recompute every track's output path, see [SplitPlan::path_for],
+    /// and name the album files after the tracks again.
+    pub(crate) fn replan_paths(&mut self, adjust: impl Fn(&Track, &mut PathParts)) -> Result<()> {
+        self.path_warnings.clear();
+        self.album_file_suffix = None;
+        for t in 0..self.tracks.len() {
+            let track = &self.tracks[t].track;
+            let output_path = self.path_for(track, |parts| adjust(track, parts))?;
+            self.set_output_path(t, output_path);
+        }
+        Ok(())
+    }
+
+    /// The planned output paths, of tracks and album files, that
+    /// already exist on disk.
+    pub fn existing_outputs(&self) -> impl Iterator<Item = PathBuf> + '_ {
+        self.tracks
+            .iter()
+            .map(|planned| planned.output_path.clone())
+            .chain(self.album_files().into_iter().map(|(path, _)| path))
+            .filter(|path| path.exists())
+    }
+
+    /// Write every planned track to its output path, returning the
+    /// paths written. Output files that already exist are handled
+    /// according to the plan's [SplitOptions::existing] policy; with
+    /// [ExistingPolicy::Fail], nothing is written if any of the
+    /// plan's output files exist.
+    ///
+    /// Tracks removed from the plan are skipped over, but the
+    /// remaining ones must be in order and may not overlap.
+    ///
+    /// Each track is written to a temporary file first and only moved
+    /// into place when complete, see [FilesystemSink]. If splitting
+    /// fails, and [SplitOptions::remove_partial] is set, the tracks
+    /// written so far are removed as well (along with the album
+    /// directories, if that leaves them empty), except for those that
+    /// replaced files which existed before.
+    pub fn execute(&self) -> Result<Vec<PathBuf>> {
+        self.execute_with_report(&mut self.report())
+    }
+
+    /// A report of this plan, with every track still
+    /// [planned](crate::TrackStatus::Planned).
+    pub fn report(&self) -> SplitReport {
+        SplitReport::new(self)
+    }
+
+    /// [Execute](SplitPlan::execute) the plan, recording what happens
+    /// to each track in `report`, which should be this plan's
+    /// [report](SplitPlan::report).
+    ///
+    /// If splitting fails, `report` still shows the tracks written
+    /// (or removed again) up to that point.
+    pub fn execute_with_report(&self, report: &mut SplitReport) -> Result<Vec<PathBuf>> {
+        if self.options.existing == ExistingPolicy::Fail
+            && let Some(path) = self.existing_outputs().next()
+        {
+            return Err(Error::OutputExists { path });
+        }
+        let existed: BTreeSet<PathBuf> = if self.options.remove_partial {
+            self.existing_outputs().collect()
+        } else {
+            BTreeSet::new()
+        };
+        let mut sink = FilesystemSink::new(self.options.existing);
+        let mut written = vec![];
+        let result = self
+            .write_tracks(&mut sink, |t, output| {
+                if output.written {
+                    written.push(self.tracks[t].output_path.clone());
+                }
+                if let Some(track) = report.tracks.get_mut(t) {
+                    track.record(output, self.cue.sample_rate);
+                }
+            })
+            .and_then(|()| self.write_album_files(&mut sink, report));
+        match result {
+            Ok(()) => Ok(written),
+            Err(err) => {
+                if self.options.remove_partial {
+                    // Files the user already had are kept, rather
+                    // than leaving nothing in their place:
+                    written.retain(|path| !existed.contains(path));
+                    remove_tracks(&written);
+                    for track in &mut report.tracks {
+                        if track.status == TrackStatus::Written
+                            && !existed.contains(&track.output_path)
+                        {
+                            track.status = TrackStatus::Removed;
+                        }
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// Write the playlists, CUE sheet and pictures (as configured in
+    /// the options) next to the written tracks.
+    fn write_album_files(&self, sink: &mut FilesystemSink, report: &mut SplitReport) -> Result<()> {
+        let mut files = vec![];
+        for format in &self.options.playlists {
+            if let Some(path) = self.album_file_path(format.extension()) {
+                let dir = path.parent().unwrap_or(Path::new(""));
+                let playlist = format.render(self, report, dir);
+                files.push((path, playlist.into_bytes()));
+            }
+        }
+        if self.options.cue_sheet
+            && let Some(path) = self.album_file_path("cue")
+            && let Some(sheet) = read_cue_sheet(&self.input_path)?
+        {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            let cue_sheet = render_cue_sheet(self, report, &sheet, dir);
+            files.push((path, cue_sheet.into_bytes()));
+        }
+        for (path, data) in self.picture_paths() {
+            files.push((path, data.to_vec()));
+        }
+        for (path, contents) in files {
+            if sink
+                .write_file(&path, &contents)
+                .map_err(Error::output_io(&path))?
+            {
+                report.album_files.push(path);
+            }
+        }
+        Ok(())
+    }
+
+    /// The directory of the first track, into which files that
+    /// accompany the whole disc image go, or `None` if no tracks are
+    /// planned.
+    fn album_dir(&self) -> Option<&Path> {
+        self.tracks.first()?.output_path.parent()
+    }
+
+    /// The path of a file with the given `extension` that accompanies
+    /// the whole disc image, such as a playlist, or `None` if no
+    /// tracks are planned.
+    ///
+    /// It goes into the directory of the first track, and is named
+    /// after it, with the disc number appended if the tracks' file
+    /// names include it (or the [album file
+    /// suffix](SplitPlan::album_file_suffix) if set).
+    pub(crate) fn album_file_path(&self, extension: &str) -> Option<PathBuf> {
+        let first = self.tracks.first()?;
+        let dir = self.album_dir()?;
+        let album = dir.file_name()?.to_string_lossy();
+        let parts = first.track.path_parts();
+        let suffix = match (&self.album_file_suffix, &parts.disc, parts.show_disc) {
+            (Some(suffix), _, _) => format!("{}.{}", suffix, extension),
+            (None, Some(disc), true) => format!(" (Disc {}).{}", disc, extension),
+            _ => format!(".{}", extension),
+        };
+        let max_bytes = self
+            .options
+            .path_limits
+            .max_component_bytes
+            .saturating_sub(suffix.len());
+        Some(dir.join(format!("{}{}", truncate_str(&album, max_bytes), suffix)))
+    }
+
+    /// The paths that the [album pictures](SplitPlan::album_pictures)
+    /// are extracted to, with their image data.
+    fn picture_paths(&self) -> Vec<(PathBuf, &[u8])> {
+        let Some(dir) = self.album_dir() else {
+            return vec![];
+        };
+        let suffix = self.album_file_suffix.as_deref().unwrap_or("");
+        picture_files(&self.album_pictures)
+            .into_iter()
+            .map(|(name, data)| {
+                let name = match name.rsplit_once('.') {
+                    Some((stem, extension)) => format!("{}{}.{}", stem, suffix, extension),
+                    None => format!("{}{}", name, suffix),
+                };
+                (dir.join(name), data)
+            })
+            .collect()
+    }
+
+    /// The paths of the files accompanying the whole disc image that
+    /// executing the plan writes, along with their contents if they
+    /// are known before the tracks are written (as for pictures).
+    pub(crate) fn album_files(&self) -> Vec<(PathBuf, Option<&[u8]>)> {
+        let mut files: Vec<_> = self
+            .options
+            .playlists
+            .iter()
+            .map(|format| format.extension())
+            .chain(self.options.cue_sheet.then_some("cue"))
+            .filter_map(|extension| Some((self.album_file_path(extension)?, None)))
+            .collect();
+        files.extend(
+            self.picture_paths()
+                .into_iter()
+                .map(|(path, data)| (path, Some(data))),
+        );
+        files
+    }
+
+    /// Write every planned track into `sink`, returning the paths of
+    /// the tracks that the sink wanted written.
+    ///
+    /// Tracks removed from the plan are skipped over, but the
+    /// remaining ones must be in order and may not overlap.
+    pub fn execute_into<S: TrackSink>(&self, sink: &mut S) -> Result<Vec<PathBuf>> {
+        let mut written = vec![];
+        self.write_tracks(sink, |t, output| {
+            if output.written {
+                written.push(self.tracks[t].output_path.clone());
+            }
+        })?;
+        Ok(written)
+    }
+
+    /// Write every planned track into `sink`, handing what happened to
+    /// the track at each index to `on_track`.
+    #[instrument(skip(self, sink, on_track), fields(input_path = ?self.input_path), err)]
+    fn write_tracks<S: TrackSink>(
+        &self,
+        sink: &mut S,
+        mut on_track: impl FnMut(usize, &TrackOutput),
+    ) -> Result<()> {
+        if self.tracks.is_empty() {
+            return Ok(());
+        }
+        for pair in self.tracks.windows(2) {
+            let (previous, track) = (&pair[0].track, &pair[1].track);
+            if track.start_ts < previous.end_ts {
+                return Err(Error::OverlappingTracks {
+                    track: track.number,
+                    start_ts: track.start_ts,
+                    previous: previous.number,
+                    previous_end_ts: previous.end_ts,
+                });
+            }
+        }
+        let (mut reader, _, file_length) = open_flac(&self.input_path)?;
+        let mut audio_buffer = Vec::with_capacity(file_length.try_into().unwrap_or(0));
+        for (t, planned) in self.tracks.iter().enumerate() {
+            audio_buffer.clear();
+            let output = planned.track.write_into(
+                &planned.output_path,
+                sink,
+                &mut reader,
+                &mut audio_buffer,
+                self.options.metadata_padding,
+                self.cue.first_track_ts,
+                &self.options.interrupt,
+            )?;
+            on_track(t, &output);
+        }
+        info!("Done with disc image");
+        Ok(())
+    }
+}
+
+/// Remove the tracks written from a disc image that failed to split,
+/// and their directories if that leaves them empty.
+fn remove_tracks(paths: &[PathBuf]) {
+    for path in paths {
+        warn!(?path, "Removing partially split album's track.");
+        if let Err(error) = fs::remove_file(path) {
+            warn!(?path, %error, "Could not remove track.");
+        }
+    }
+    let dirs: BTreeSet<&Path> = paths.iter().filter_map(|path| path.parent()).collect();
+    for dir in dirs {
+        // Only succeeds if the album (and then, the artist) directory
+        // is empty, which is the point:
+        if fs::remove_dir(dir).is_ok()
+            && let Some(artist_dir) = dir.parent()
+        {
+            let _ = fs::remove_dir(artist_dir);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cues(starts: &[(u32, u64)]) -> Vec<Cue> {
+        starts
+            .iter()
+            .map(|&(index, start_ts)| Cue {
+                index,
+                start_ts,
+                tags: vec![],
+                points: vec![],
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cue_validation() {
+        let lead_out = LEAD_OUT_TRACK_NUMBER;
+        assert!(validate_cues(&cues(&[(1, 0), (2, 500), (lead_out, 1000)]), 1000).is_ok());
+        assert!(validate_cues(&cues(&[(1, 0), (2, 500)]), 1000).is_ok());
+
+        // out of order or overlapping:
+        assert!(validate_cues(&cues(&[(1, 500), (2, 0)]), 1000).is_err());
+        assert!(validate_cues(&cues(&[(1, 0), (2, 0)]), 1000).is_err());
+        // past the end of the stream:
+        assert!(matches!(
+            validate_cues(&cues(&[(1, 0), (2, 1000)]), 1000),
+            Err(Error::CueOutOfRange { track: 2, .. })
+        ));
+        assert!(matches!(
+            validate_cues(&cues(&[(1, 0), (lead_out, 1001)]), 1000),
+            Err(Error::CueOutOfRange { .. })
+        ));
+        // misplaced lead-out:
+        assert!(validate_cues(&cues(&[(lead_out, 0), (1, 500)]), 1000).is_err());
+        assert!(validate_cues(&cues(&[(1, 0), (lead_out, 500), (2, 600)]), 1000).is_err());
+
+        // unknown stream length:
+        assert!(validate_cues(&cues(&[(1, 0), (2, 5000), (lead_out, 9000)]), 0).is_ok());
+        assert!(validate_cues(&cues(&[(1, 500), (2, 0)]), 0).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use symphonia_core::meta::{Tag, Value};
+
+use crate::{
+    CueSummary, END_OF_STREAM_TS, Error, PictureBlock, PlannedTrack, Provenance, Result,
+    SplitOptions, SplitPlan, Track, plan::read_source,
+};
+
+/// The version of the plan file format written by [PlanFile::from_plans].
+pub const PLAN_FILE_VERSION: u32 = 1;
+
+/// A serializable representation of [SplitPlan]s, meant to be written
+/// out for review, edited by hand, and turned back into plans that
+/// get executed exactly as written.
+///
+/// Audio and pictures are not part of the representation; they are
+/// read from the input files again when the plans are loaded.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanFile {
+    pub version: u32,
+    pub plans: Vec<PlanEntry>,
+}
+
+/// A [SplitPlan] in a [PlanFile].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PlanEntry {
+    pub input_path: PathBuf,
+    pub tracks: Vec<TrackEntry>,
+}
+
+/// A [PlannedTrack] in a [PlanFile].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrackEntry {
+    pub number: u32,
+    pub output_path: PathBuf,
+    pub start_ts: u64,
+    /// `None` if the track runs until the end of the stream.
+    pub end_ts: Option<u64>,
+    pub tags: Vec<TagEntry>,
+    pub visuals: Vec<VisualEntry>,
+    /// The number of the CUE sheet track that the track is split
+    /// from, if it differs from `number`; recorded with
+    /// [SplitOptions::provenance]. Without it, a pregap track 0 is
+    /// taken to be split from the CUE sheet's first track.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cue_track: Option<u32>,
+}
+
+/// A vorbis comment in a [TrackEntry].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TagEntry {
+    pub key: String,
+    pub value: String,
+}
+
+/// A picture in a [TrackEntry], identified by its position among the
+/// input file's pictures. The other fields are informational only.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VisualEntry {
+    pub index: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<String>,
+    #[serde(default)]
+    pub media_type: String,
+    #[serde(default)]
+    pub bytes: usize,
+}
+
+impl PlanFile {
+    /// Represent `plans` for writing them to a file.
+    ///
+    /// Fails if a track's pictures aren't among its input file's
+    /// pictures.
+    pub fn from_plans(plans: &[SplitPlan]) -> Result<Self> {
+        let plans = plans
+            .iter()
+            .map(|plan| {
+                let (_, source_pictures, _) = read_source(plan.input_path(), plan.options())?;
+                let tracks = plan
+                    .tracks()
+                    .iter()
+                    .map(|planned| TrackEntry::new(planned, &source_pictures))
+                    .collect::<Result<_>>()?;
+                Ok(PlanEntry {
+                    input_path: plan.input_path().to_path_buf(),
+                    tracks,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(PlanFile {
+            version: PLAN_FILE_VERSION,
+            plans,
+        })
+    }
+
+    /// Turn the represented plans back into [SplitPlan]s, reading
+    /// each input file's stream info and pictures.
+    ///
+    /// The tracks' output paths are used as they are, but get
+    /// recomputed according to `options` if the plans get adjusted,
+    /// e.g. by [resolve_collisions](crate::resolve_collisions).
+    pub fn into_plans(self, options: &SplitOptions) -> Result<Vec<SplitPlan>> {
+        self.check_version()?;
+        self.plans
+            .into_iter()
+            .map(|entry| entry.into_plan(options))
+            .collect()
+    }
+
+    /// Fails if the plan file was written in a format version this
+    /// version of flac-tracksplit can't read.
+    pub fn check_version(&self) -> Result<()> {
+        if self.version != PLAN_FILE_VERSION {
+            return Err(Error::InvalidPlanFile {
+                reason: format!(
+                    "unsupported version {}, expected {}",
+                    self.version, PLAN_FILE_VERSION
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+impl PlanEntry {
+    /// Turn the represented plan back into a [SplitPlan], see
+    /// [PlanFile::into_plans].
+    pub fn into_plan(self, options: &SplitOptions) -> Result<SplitPlan> {
+        let (streaminfo, source_pictures, cues) = read_source(&self.input_path, options)?;
+        // A pregap track 0 is split from the first CUE sheet track:
+        let pregap_cue_track = cues.first().map_or(0, |cue| cue.index);
+        let tracks = self
+            .tracks
+            .into_iter()
+            .map(|entry| {
+                let end_ts = match entry.end_ts {
+                    None => END_OF_STREAM_TS,
+                    Some(end_ts) => {
+                        if streaminfo.total_samples != 0 && end_ts > streaminfo.total_samples {
+                            return Err(Error::InvalidPlanFile {
+                                reason: format!(
+                                    "track {} ends at sample {}, past the end of the stream ({} samples)",
+                                    entry.number, end_ts, streaminfo.total_samples
+                                ),
+                            });
+                        }
+                        end_ts
+                    }
+                };
+                let tags = entry
+                    .tags
+                    .into_iter()
+                    .map(|tag| Tag::new(None, &tag.key, Value::String(tag.value)))
+                    .collect();
+                let pictures = entry
+                    .visuals
+                    .iter()
+                    .map(|visual| {
+                        source_pictures.get(visual.index).cloned().ok_or_else(|| {
+                            Error::InvalidPlanFile {
+                                reason: format!(
+                                    "track {} refers to picture {}, but the input has {}",
+                                    entry.number,
+                                    visual.index,
+                                    source_pictures.len()
+                                ),
+                            }
+                        })
+                    })
+                    .collect::<Result<_>>()?;
+                let mut track = Track::new(
+                    &streaminfo,
+                    entry.number,
+                    entry.start_ts,
+                    end_ts,
+                    tags,
+                    pictures,
+                )?;
+                track.provenance = options.provenance.then(|| {
+                    let cue_track = entry.cue_track.unwrap_or(match entry.number {
+                        0 => pregap_cue_track,
+                        number => number,
+                    });
+                    Provenance::new(&self.input_path, &streaminfo, cue_track)
+                });
+                Ok(PlannedTrack {
+                    track,
+                    output_path: entry.output_path,
+                })
+            })
+            .collect::<Result<_>>()?;
+        let mut plan = SplitPlan::with_tracks(
+            &self.input_path,
+            options,
+            CueSummary::new(&streaminfo, &cues),
+            tracks,
+        );
+        if options.extract_pictures {
+            plan.album_pictures = source_pictures;
+        }
+        Ok(plan)
+    }
+}
+
+impl TrackEntry {
+    fn new(planned: &PlannedTrack, source_pictures: &[PictureBlock]) -> Result<Self> {
+        let track = &planned.track;
+        let visuals = track
+            .pictures
+            .iter()
+            .map(|picture| {
+                let index = source_pictures
+                    .iter()
+                    .position(|source| source == picture)
+                    .ok_or_else(|| Error::InvalidPlanFile {
+                        reason: format!(
+                            "track {} has a picture that is not in the input file",
+                            track.number
+                        ),
+                    })?;
+                Ok(VisualEntry {
+                    index,
+                    usage: picture.picture_type_name().map(str::to_string),
+                    media_type: picture.mime_type().unwrap_or_default().into_owned(),
+                    bytes: picture.data().map_or(0, <[u8]>::len),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(TrackEntry {
+            number: track.number,
+            output_path: planned.output_path.clone(),
+            start_ts: track.start_ts,
+            end_ts: (track.end_ts != END_OF_STREAM_TS).then_some(track.end_ts),
+            tags: track
+                .tags
+                .iter()
+                .map(|tag| TagEntry {
+                    key: tag.key.clone(),
+                    value: tag.value.to_string(),
+                })
+                .collect(),
+            visuals,
+            cue_track: track
+                .provenance
+                .as_ref()
+                .map(|provenance| provenance.cue_track)
+                .filter(|&cue_track| cue_track != track.number),
+        })
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    fmt::{self, Write},
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+use symphonia_core::meta::Value;
+
+use crate::{END_OF_STREAM_TS, Error, SplitPlan, SplitReport, Track};
+
+/// A format of the album playlists written along with the tracks, see
+/// [SplitOptions::playlists](crate::SplitOptions::playlists).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PlaylistFormat {
+    /// An extended M3U playlist in UTF-8, with `#EXTINF` durations and
+    /// titles.
+    M3u8,
+
+    /// An [XSPF](https://xspf.org/) playlist.
+    Xspf,
+}
+
+impl PlaylistFormat {
+    /// The playlist file name extension, without a leading dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PlaylistFormat::M3u8 => "m3u8",
+            PlaylistFormat::Xspf => "xspf",
+        }
+    }
+
+    /// Render the playlist of `plan`'s tracks, to be written to a
+    /// file in `dir`. `report` provides the tracks' actual durations,
+    /// if they have been split.
+    pub(crate) fn render(&self, plan: &SplitPlan, report: &SplitReport, dir: &Path) -> String {
+        let entries = entries(plan, report, dir);
+        match self {
+            PlaylistFormat::M3u8 => render_m3u8(&entries),
+            PlaylistFormat::Xspf => render_xspf(plan, &entries),
+        }
+    }
+}
+
+impl FromStr for PlaylistFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "m3u8" => Ok(PlaylistFormat::M3u8),
+            "xspf" => Ok(PlaylistFormat::Xspf),
+            _ => Err(Error::UnknownOption {
+                option: "playlist format",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for PlaylistFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+/// A track in a playlist.
+struct Entry<'a> {
+    /// The track's path, relative to the playlist's directory, with
+    /// `/` separators.
+    location: String,
+    number: u32,
+    duration_secs: Option<f64>,
+    artist: Option<&'a str>,
+    title: Option<&'a str>,
+}
+
+fn tag<'a>(track: &'a Track, name: &str) -> Option<&'a str> {
+    match track.tag_value(name) {
+        Some(Value::String(value)) => Some(value.as_str()),
+        _ => None,
+    }
+}
+
+fn entries<'a>(plan: &'a SplitPlan, report: &SplitReport, dir: &Path) -> Vec<Entry<'a>> {
+    let sample_rate = report.cue.sample_rate;
+    plan.tracks()
+        .iter()
+        .enumerate()
+        .map(|(t, planned)| {
+            let track = &planned.track;
+            let planned_duration = (track.end_ts != END_OF_STREAM_TS && sample_rate != 0)
+                .then(|| (track.end_ts - track.start_ts) as f64 / sample_rate as f64);
+            Entry {
+                location: relative_location(dir, &planned.output_path),
+                number: track.number,
+                duration_secs: report
+                    .tracks
+                    .get(t)
+                    .and_then(|track| track.duration_secs)
+                    .or(planned_duration),
+                artist: tag(track, "ARTIST").or_else(|| tag(track, "ALBUMARTIST")),
+                title: tag(track, "TITLE"),
+            }
+        })
+        .collect()
+}
+
+/// The path of `path` relative to the directory `dir`, with `/`
+/// separators.
+pub(crate) fn relative_location(dir: &Path, path: &Path) -> String {
+    let dir: Vec<Component> = dir.components().collect();
+    let path: Vec<Component> = path.components().collect();
+    let common = dir.iter().zip(&path).take_while(|(a, b)| a == b).count();
+    let relative: PathBuf = std::iter::repeat_n(Component::ParentDir, dir.len() - common)
+        .chain(path[common..].iter().copied())
+        .collect();
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn render_m3u8(entries: &[Entry]) -> String {
+    let mut out = String::from("#EXTM3U\n");
+    for entry in entries {
+        let duration = entry.duration_secs.map_or(-1, |secs| secs.round() as i64);
+        let display = match (entry.artist, entry.title) {
+            (Some(artist), Some(title)) => format!("{} - {}", artist, title),
+            (None, Some(title)) => title.to_string(),
+            (_, None) => format!("Track {}", entry.number),
+        };
+        // Line breaks would end the directive early:
+        let display = display.replace(['\r', '\n'], " ");
+        let _ = writeln!(out, "#EXTINF:{},{}", duration, display);
+        let _ = writeln!(out, "{}", entry.location);
+    }
+    out
+}
+
+fn render_xspf(plan: &SplitPlan, entries: &[Entry]) -> String {
+    let first = plan.tracks().first().map(|planned| &planned.track);
+    let album = first.and_then(|track| tag(track, "ALBUM"));
+    let album_artist =
+        first.and_then(|track| tag(track, "ALBUMARTIST").or_else(|| tag(track, "ARTIST")));
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<playlist version=\"1\" xmlns=\"http://xspf.org/ns/0/\">\n");
+    if let Some(album) = album {
+        let _ = writeln!(out, "  <title>{}</title>", xml_escape(album));
+    }
+    if let Some(artist) = album_artist {
+        let _ = writeln!(out, "  <creator>{}</creator>", xml_escape(artist));
+    }
+    out.push_str("  <trackList>\n");
+    for entry in entries {
+        out.push_str("    <track>\n");
+        let _ = writeln!(
+            out,
+            "      <location>{}</location>",
+            xml_escape(&uri_escape(&entry.location))
+        );
+        if let Some(title) = entry.title {
+            let _ = writeln!(out, "      <title>{}</title>", xml_escape(title));
+        }
+        if let Some(artist) = entry.artist {
+            let _ = writeln!(out, "      <creator>{}</creator>", xml_escape(artist));
+        }
+        if let Some(album) = album {
+            let _ = writeln!(out, "      <album>{}</album>", xml_escape(album));
+        }
+        let _ = writeln!(out, "      <trackNum>{}</trackNum>", entry.number);
+        if let Some(secs) = entry.duration_secs {
+            let _ = writeln!(
+                out,
+                "      <duration>{}</duration>",
+                (secs * 1000.0).round() as u64
+            );
+        }
+        out.push_str("    </track>\n");
+    }
+    out.push_str("  </trackList>\n</playlist>\n");
+    out
+}
+
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            // Not allowed in XML 1.0 documents at all:
+            c if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Percent-encode a relative path for use as a URI reference.
+fn uri_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                out.push(byte as char)
+            }
+            _ => {
+                let _ = write!(out, "%{:02X}", byte);
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn relative_locations() {
+        let dir = Path::new("/out/Artist/2001 - Album");
+        assert_eq!(
+            relative_location(dir, Path::new("/out/Artist/2001 - Album/01.One.flac")),
+            "01.One.flac"
+        );
+        assert_eq!(
+            relative_location(
+                dir,
+                Path::new("/out/Artist/2001 - Album [CAT-1]/01.One.flac")
+            ),
+            "../2001 - Album [CAT-1]/01.One.flac"
+        );
+        assert_eq!(
+            uri_escape("../2001 - Album/01.Ünë.flac"),
+            "../2001%20-%20Album/01.%C3%9Cn%C3%AB.flac"
+        );
+    }
+}
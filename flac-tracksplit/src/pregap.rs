@@ -0,0 +1,45 @@
+use std::{fmt, str::FromStr};
+
+use crate::Error;
+
+/// What to do with the audio before the first track's INDEX 01, such
+/// as a hidden track, if the CUE sheet has an INDEX 00 before it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PregapPolicy {
+    /// Split it into a track 0 of its own, which gets the tags of
+    /// track 0 if there are any, and the first track's otherwise.
+    #[default]
+    Split,
+
+    /// Keep it at the start of the first track.
+    Prepend,
+
+    /// Leave it out: The first track starts at its INDEX 01.
+    Drop,
+}
+
+impl FromStr for PregapPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "split" => Ok(PregapPolicy::Split),
+            "prepend" => Ok(PregapPolicy::Prepend),
+            "drop" => Ok(PregapPolicy::Drop),
+            _ => Err(Error::UnknownOption {
+                option: "pregap policy",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for PregapPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PregapPolicy::Split => "split",
+            PregapPolicy::Prepend => "prepend",
+            PregapPolicy::Drop => "drop",
+        })
+    }
+}
@@ -0,0 +1,115 @@
+use metaflac::block::StreamInfo;
+use std::{fmt::Write, path::Path};
+
+use crate::{END_OF_STREAM_TS, Track};
+
+/// How tracks are cut out of a disc image, as recorded in the
+/// `SOURCE_BOUNDARY` tag: at frame boundaries, with a frame that
+/// holds samples of two tracks going to the earlier one, whether or
+/// not that one is written as well.
+pub const BOUNDARY_POLICY: &str = "frame-to-earlier-track";
+
+/// Where a track was split from, written to its vorbis comments if
+/// set, see [SplitOptions::provenance](crate::SplitOptions::provenance):
+///
+/// * `SOURCE_FILE`: the disc image's file name.
+/// * `SOURCE_MD5`: the MD5 signature of the disc image's audio, from
+///   its STREAMINFO, in hex (if it has one).
+/// * `SOURCE_START_SAMPLE`, `SOURCE_END_SAMPLE`: where the track
+///   starts and ends in the disc image according to the CUE sheet (or
+///   the plan). Its frames start and end at the frame boundaries
+///   around these, according to `SOURCE_BOUNDARY`.
+/// * `SOURCE_CUE_TRACK`: the number of the CUE sheet track it was
+///   split from.
+/// * `SOURCE_BOUNDARY`: the [BOUNDARY_POLICY].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    pub source_file: String,
+    pub source_md5: Option<String>,
+    pub cue_track: u32,
+}
+
+impl Provenance {
+    /// The provenance of a track split from CUE sheet track
+    /// `cue_track` of the disc image at `input_path`, whose STREAMINFO
+    /// is `streaminfo`.
+    pub fn new(input_path: &Path, streaminfo: &StreamInfo, cue_track: u32) -> Self {
+        let source_md5 = streaminfo.md5.iter().any(|&byte| byte != 0).then(|| {
+            streaminfo.md5.iter().fold(String::new(), |mut hex, byte| {
+                let _ = write!(hex, "{:02x}", byte);
+                hex
+            })
+        });
+        Self {
+            source_file: input_path
+                .file_name()
+                .unwrap_or(input_path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            source_md5,
+            cue_track,
+        }
+    }
+
+    /// The vorbis comments recording the provenance of `track`, which
+    /// has `total_samples` samples.
+    pub(crate) fn tags(&self, track: &Track, total_samples: u64) -> Vec<(&'static str, String)> {
+        let end_ts = if track.end_ts == END_OF_STREAM_TS {
+            track.start_ts + total_samples
+        } else {
+            track.end_ts
+        };
+        let mut tags = vec![("SOURCE_FILE", self.source_file.clone())];
+        if let Some(md5) = &self.source_md5 {
+            tags.push(("SOURCE_MD5", md5.clone()));
+        }
+        tags.extend([
+            ("SOURCE_START_SAMPLE", track.start_ts.to_string()),
+            ("SOURCE_END_SAMPLE", end_ts.to_string()),
+            ("SOURCE_CUE_TRACK", self.cue_track.to_string()),
+            ("SOURCE_BOUNDARY", BOUNDARY_POLICY.to_string()),
+        ]);
+        tags
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn source_tags() {
+        let mut info = StreamInfo::new();
+        info.md5 = (0..16).collect();
+        let input_path = Path::new("/images/album.flac");
+        // A pregap split from CUE track 1, and a last track running
+        // until the end of a stream of unknown length:
+        let pregap = Track::new(&info, 0, 0, 44100, vec![], vec![]).unwrap();
+        let last = Track::new(&info, 3, 88200, END_OF_STREAM_TS, vec![], vec![]).unwrap();
+
+        assert_eq!(
+            Provenance::new(input_path, &info, 1).tags(&pregap, 44100),
+            [
+                ("SOURCE_FILE", "album.flac".to_string()),
+                ("SOURCE_MD5", "000102030405060708090a0b0c0d0e0f".to_string()),
+                ("SOURCE_START_SAMPLE", "0".to_string()),
+                ("SOURCE_END_SAMPLE", "44100".to_string()),
+                ("SOURCE_CUE_TRACK", "1".to_string()),
+                ("SOURCE_BOUNDARY", "frame-to-earlier-track".to_string()),
+            ]
+        );
+
+        // Without an MD5 signature:
+        info.md5 = vec![0; 16];
+        assert_eq!(
+            Provenance::new(input_path, &info, 3).tags(&last, 1000),
+            [
+                ("SOURCE_FILE", "album.flac".to_string()),
+                ("SOURCE_START_SAMPLE", "88200".to_string()),
+                ("SOURCE_END_SAMPLE", "89200".to_string()),
+                ("SOURCE_CUE_TRACK", "3".to_string()),
+                ("SOURCE_BOUNDARY", "frame-to-earlier-track".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use metaflac::block::StreamInfo;
+use serde::Serialize;
+use std::path::PathBuf;
+use symphonia_core::formats::Cue;
+
+use crate::{END_OF_STREAM_TS, LEAD_OUT_TRACK_NUMBER, SplitPlan, TagEntry, TrackOutput};
+
+/// A machine-readable account of splitting a disc image: what its CUE
+/// sheet describes, and what happened to each planned track.
+///
+/// Created by [SplitPlan::report] with every track still
+/// [TrackStatus::Planned], and filled in by
+/// [SplitPlan::execute_with_report].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SplitReport {
+    pub input_path: PathBuf,
+    pub cue: CueSummary,
+    pub tracks: Vec<TrackReport>,
+    /// The files written for the album as a whole, such as
+    /// playlists.
+    pub album_files: Vec<PathBuf>,
+    /// Things about the disc image worth a second look.
+    pub warnings: Vec<String>,
+}
+
+/// The stream and CUE sheet of a disc image, in a [SplitReport].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CueSummary {
+    pub sample_rate: u32,
+    pub channels: u8,
+    pub bits_per_sample: u8,
+    /// `None` if the stream doesn't record its length.
+    pub total_samples: Option<u64>,
+    /// The number of tracks in the CUE sheet, not counting the
+    /// lead-out.
+    pub tracks: usize,
+    /// Where the lead-out starts, if the CUE sheet has one.
+    pub lead_out_ts: Option<u64>,
+    /// Where the CUE sheet's first track (including its pregap)
+    /// starts. Frames starting after this belong to the track that
+    /// their first sample is in.
+    pub first_track_ts: u64,
+}
+
+/// What happened to a planned track, in a [SplitReport].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TrackStatus {
+    /// Not (yet) written.
+    Planned,
+    /// Written to its output path.
+    Written,
+    /// Left alone, as its output already exists (or is unchanged).
+    Skipped,
+    /// Written, but removed again as splitting the disc image failed
+    /// later on, see [SplitOptions::remove_partial](crate::SplitOptions::remove_partial).
+    Removed,
+}
+
+/// A planned track in a [SplitReport].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrackReport {
+    pub number: u32,
+    pub output_path: PathBuf,
+    pub status: TrackStatus,
+    /// Where the track starts according to the plan, in samples.
+    pub start_ts: u64,
+    /// Where the track ends according to the plan, or `None` if it
+    /// runs until the end of the stream.
+    pub end_ts: Option<u64>,
+    /// The first sample of the track's first frame, once its audio
+    /// has been read.
+    pub first_sample: Option<u64>,
+    /// The number of samples in the track's frames.
+    pub samples: Option<u64>,
+    /// The duration of the track's frames, in seconds.
+    pub duration_secs: Option<f64>,
+    /// The size of the track's file, if it was written.
+    pub bytes: Option<u64>,
+    /// How many samples after `start_ts` the track's first frame
+    /// starts. Tracks are split at frame boundaries, so a track may
+    /// start early (negative), with samples of the previous track, or
+    /// late (positive), if the frame holding `start_ts` went to the
+    /// previous track.
+    pub start_error: Option<i64>,
+    /// How many samples after `end_ts` the track's last frame ends,
+    /// i.e. how many samples of the next track it includes.
+    pub end_error: Option<i64>,
+    /// The vorbis comments written to the track; until it is written,
+    /// the track's tags.
+    pub tags: Vec<TagEntry>,
+    /// Things about the track worth a second look.
+    pub warnings: Vec<String>,
+}
+
+impl CueSummary {
+    pub(crate) fn new(info: &StreamInfo, cues: &[Cue]) -> Self {
+        let lead_out_ts = cues
+            .iter()
+            .find(|cue| cue.index == LEAD_OUT_TRACK_NUMBER)
+            .map(|cue| cue.start_ts);
+        CueSummary {
+            sample_rate: info.sample_rate,
+            channels: info.num_channels,
+            bits_per_sample: info.bits_per_sample,
+            total_samples: (info.total_samples != 0).then_some(info.total_samples),
+            tracks: cues
+                .iter()
+                .filter(|cue| cue.index != LEAD_OUT_TRACK_NUMBER)
+                .count(),
+            lead_out_ts,
+            first_track_ts: cues.first().map_or(0, |cue| cue.start_ts),
+        }
+    }
+}
+
+impl SplitReport {
+    pub(crate) fn new(plan: &SplitPlan) -> Self {
+        let cue = plan.cue.clone();
+        let mut warnings = vec![];
+        if cue.total_samples.is_none() {
+            warnings.push("the stream doesn't record its total number of samples".to_string());
+        }
+        warnings.extend(plan.warnings.iter().cloned());
+        let base_path = plan.options().base_path();
+        let tracks = plan
+            .tracks()
+            .iter()
+            .map(|planned| {
+                let track = &planned.track;
+                let mut warnings = track.warnings.clone();
+                if let Some(path_warnings) = plan.path_warnings.get(&planned.output_path) {
+                    warnings.extend(path_warnings.iter().cloned());
+                }
+                let tag_path = base_path.join(track.pathname());
+                if planned.output_path != tag_path {
+                    warnings.push(format!(
+                        "output path differs from {:?}, as derived from the track's tags",
+                        tag_path
+                    ));
+                }
+                TrackReport {
+                    number: track.number,
+                    output_path: planned.output_path.clone(),
+                    status: TrackStatus::Planned,
+                    start_ts: track.start_ts,
+                    end_ts: (track.end_ts != END_OF_STREAM_TS).then_some(track.end_ts),
+                    first_sample: None,
+                    samples: None,
+                    duration_secs: None,
+                    bytes: None,
+                    start_error: None,
+                    end_error: None,
+                    tags: track
+                        .tags
+                        .iter()
+                        .map(|tag| TagEntry {
+                            key: tag.key.clone(),
+                            value: tag.value.to_string(),
+                        })
+                        .collect(),
+                    warnings,
+                }
+            })
+            .collect();
+        SplitReport {
+            input_path: plan.input_path().to_path_buf(),
+            cue,
+            tracks,
+            album_files: vec![],
+            warnings,
+        }
+    }
+}
+
+impl TrackReport {
+    /// Record what writing the track did.
+    pub(crate) fn record(&mut self, output: &TrackOutput, sample_rate: u32) {
+        let audio = output.audio;
+        let end = audio.first_ts + audio.samples;
+        self.status = if output.written {
+            TrackStatus::Written
+        } else {
+            TrackStatus::Skipped
+        };
+        self.tags = output.tags.clone();
+        self.first_sample = Some(audio.first_ts);
+        self.samples = Some(audio.samples);
+        self.duration_secs = (sample_rate != 0).then(|| audio.samples as f64 / sample_rate as f64);
+        self.bytes = output.written.then_some(output.bytes);
+        self.start_error = Some(difference(audio.first_ts, self.start_ts));
+        self.end_error = Some(match self.end_ts {
+            Some(end_ts) => difference(end, end_ts),
+            None => 0,
+        });
+    }
+}
+
+/// `a - b`, saturating at the bounds of [i64].
+fn difference(a: u64, b: u64) -> i64 {
+    let difference = i128::from(a) - i128::from(b);
+    difference
+        .clamp(i64::MIN.into(), i64::MAX.into())
+        .try_into()
+        .unwrap_or_default()
+}
@@ -0,0 +1,325 @@
+use regex::Regex;
+use serde::Deserialize;
+use std::{collections::BTreeMap, str::FromStr};
+use symphonia_core::meta::{Tag, Value};
+use tracing::debug;
+
+use crate::{Error, Track};
+
+/// Rules that rewrite the tags of split tracks, read from a TOML file
+/// with a `[[rule]]` table for each rule, see
+/// [SplitOptions::tag_rules](crate::SplitOptions::tag_rules):
+///
+/// ```toml
+/// [[rule]]
+/// drop = ["COMMENT", "ENCODER"]
+/// rename = { ORGANIZATION = "LABEL" }
+///
+/// [[rule]]
+/// when = { ALBUMARTIST = "Various Artists" }
+/// set = { COMPILATION = "1" }
+///
+/// [[rule]]
+/// tracks = [1, 2]
+/// when_matches = { TITLE = "(?i)live" }
+/// add = { GENRE = "Live" }
+/// rewrite = [{ key = "TITLE", pattern = " \\(Remastered\\)$", replacement = "" }]
+/// ```
+///
+/// Rules apply to each track in order. A rule applies to a track if
+/// all of its conditions hold:
+///
+/// * `tracks`: the track number is one of these.
+/// * `when`: each key has a value equal to the given one.
+/// * `when_matches`: each key has a value that the given regular
+///   expression matches.
+///
+/// and then makes its changes in this order:
+///
+/// * `drop`: remove all values of these keys.
+/// * `rename`: change keys to new ones, keeping their values.
+/// * `set`: replace all values of keys with the given one (adding
+///   the key if it isn't tagged).
+/// * `add`: add another value to keys.
+/// * `rewrite`: replace every match of the `pattern` in each value of
+///   `key` with the `replacement`, which can refer to capture groups
+///   as `$1` or `${name}`.
+///
+/// Keys are compared ignoring case.
+#[derive(Debug, Clone, Default)]
+pub struct TagRules {
+    rules: Vec<TagRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RulesFile {
+    #[serde(default)]
+    rule: Vec<RuleEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RuleEntry {
+    tracks: Option<Vec<u32>>,
+    #[serde(default)]
+    when: BTreeMap<String, String>,
+    #[serde(default)]
+    when_matches: BTreeMap<String, String>,
+    #[serde(default)]
+    drop: Vec<String>,
+    #[serde(default)]
+    rename: BTreeMap<String, String>,
+    #[serde(default)]
+    set: BTreeMap<String, String>,
+    #[serde(default)]
+    add: BTreeMap<String, String>,
+    #[serde(default)]
+    rewrite: Vec<RewriteEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RewriteEntry {
+    key: String,
+    pattern: String,
+    replacement: String,
+}
+
+#[derive(Debug, Clone)]
+struct TagRule {
+    tracks: Option<Vec<u32>>,
+    when: Vec<(String, String)>,
+    when_matches: Vec<(String, Regex)>,
+    drop: Vec<String>,
+    rename: Vec<(String, String)>,
+    set: Vec<(String, String)>,
+    add: Vec<(String, String)>,
+    rewrite: Vec<(String, Regex, String)>,
+}
+
+fn regex(rule: usize, pattern: &str) -> Result<Regex, Error> {
+    Regex::new(pattern).map_err(|err| Error::InvalidTagRules {
+        reason: format!("rule {}: {}", rule, err),
+    })
+}
+
+impl FromStr for TagRules {
+    type Err = Error;
+
+    /// Parse the TOML rules file `s`, failing on unknown fields and
+    /// invalid regular expressions.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let file: RulesFile = toml::from_str(s).map_err(|err| Error::InvalidTagRules {
+            reason: err.to_string(),
+        })?;
+        let rules = file
+            .rule
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| {
+                let number = i + 1;
+                Ok(TagRule {
+                    tracks: entry.tracks,
+                    when: entry.when.into_iter().collect(),
+                    when_matches: entry
+                        .when_matches
+                        .into_iter()
+                        .map(|(key, pattern)| Ok((key, regex(number, &pattern)?)))
+                        .collect::<Result<_, Error>>()?,
+                    drop: entry.drop,
+                    rename: entry.rename.into_iter().collect(),
+                    set: entry.set.into_iter().collect(),
+                    add: entry.add.into_iter().collect(),
+                    rewrite: entry
+                        .rewrite
+                        .into_iter()
+                        .map(|rewrite| {
+                            Ok((
+                                rewrite.key,
+                                regex(number, &rewrite.pattern)?,
+                                rewrite.replacement,
+                            ))
+                        })
+                        .collect::<Result<_, Error>>()?,
+                })
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(TagRules { rules })
+    }
+}
+
+fn values<'a>(tags: &'a [Tag], key: &'a str) -> impl Iterator<Item = String> + 'a {
+    tags.iter()
+        .filter(move |tag| tag.key.eq_ignore_ascii_case(key))
+        .map(|tag| tag.value.to_string())
+}
+
+fn string_tag(key: &str, value: impl Into<String>) -> Tag {
+    Tag::new(None, key, Value::String(value.into()))
+}
+
+impl TagRule {
+    fn applies(&self, track: &Track) -> bool {
+        self.tracks
+            .as_ref()
+            .is_none_or(|tracks| tracks.contains(&track.number))
+            && self
+                .when
+                .iter()
+                .all(|(key, expected)| values(&track.tags, key).any(|value| value == *expected))
+            && self.when_matches.iter().all(|(key, pattern)| {
+                values(&track.tags, key).any(|value| pattern.is_match(&value))
+            })
+    }
+
+    fn apply(&self, tags: &mut Vec<Tag>) {
+        tags.retain(|tag| {
+            !self
+                .drop
+                .iter()
+                .any(|key| tag.key.eq_ignore_ascii_case(key))
+        });
+        for (from, to) in &self.rename {
+            for tag in tags.iter_mut() {
+                if tag.key.eq_ignore_ascii_case(from) {
+                    *tag = Tag::new(tag.std_key, to, tag.value.clone());
+                }
+            }
+        }
+        for (key, value) in &self.set {
+            // The first value is replaced in place, later ones dropped:
+            let mut found = false;
+            tags.retain_mut(|tag| {
+                if !tag.key.eq_ignore_ascii_case(key) {
+                    return true;
+                }
+                if !found {
+                    found = true;
+                    *tag = Tag::new(tag.std_key, key, Value::String(value.clone()));
+                    return true;
+                }
+                false
+            });
+            if !found {
+                tags.push(string_tag(key, value.clone()));
+            }
+        }
+        for (key, value) in &self.add {
+            tags.push(string_tag(key, value.clone()));
+        }
+        for (key, pattern, replacement) in &self.rewrite {
+            for tag in tags.iter_mut() {
+                if !tag.key.eq_ignore_ascii_case(key) {
+                    continue;
+                }
+                let value = tag.value.to_string();
+                let rewritten = pattern.replace_all(&value, replacement.as_str());
+                if rewritten != value {
+                    *tag = Tag::new(tag.std_key, &tag.key, Value::String(rewritten.into_owned()));
+                }
+            }
+        }
+    }
+}
+
+impl TagRules {
+    /// Whether there are no rules at all.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rewrite `track`'s tags with each rule that applies to it, in
+    /// order.
+    pub(crate) fn apply(&self, track: &mut Track) {
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.applies(track) {
+                debug!(track = track.number, rule = i + 1, "Applying tag rule.");
+                rule.apply(&mut track.tags);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use metaflac::block::StreamInfo;
+
+    fn track(number: u32, tags: &[(&str, &str)]) -> Track {
+        let tags = tags
+            .iter()
+            .map(|(key, value)| string_tag(key, *value))
+            .collect();
+        Track::new(&StreamInfo::new(), number, 0, 100, tags, vec![]).unwrap()
+    }
+
+    fn pairs(track: &Track) -> Vec<(String, String)> {
+        track
+            .tags
+            .iter()
+            .map(|tag| (tag.key.clone(), tag.value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn rewriting() {
+        let rules: TagRules = r#"
+            [[rule]]
+            drop = ["comment"]
+            rename = { ORGANIZATION = "LABEL" }
+
+            [[rule]]
+            when = { ALBUMARTIST = "Various Artists" }
+            set = { COMPILATION = "1" }
+
+            [[rule]]
+            tracks = [2]
+            rewrite = [{ key = "TITLE", pattern = " \\(Remastered( \\d+)?\\)$", replacement = "" }]
+            add = { GENRE = "Pop" }
+        "#
+        .parse()
+        .unwrap();
+        let mut one = track(
+            1,
+            &[
+                ("ALBUMARTIST", "Various Artists"),
+                ("COMMENT", "Ripped"),
+                ("Organization", "Label"),
+                ("TITLE", "One (Remastered)"),
+                ("COMPILATION", "0"),
+                ("COMPILATION", "yes"),
+            ],
+        );
+        rules.apply(&mut one);
+        assert_eq!(
+            pairs(&one),
+            pairs(&track(
+                1,
+                &[
+                    ("ALBUMARTIST", "Various Artists"),
+                    ("LABEL", "Label"),
+                    ("TITLE", "One (Remastered)"),
+                    ("COMPILATION", "1"),
+                ]
+            ))
+        );
+
+        let mut two = track(2, &[("TITLE", "Two (Remastered 2011)"), ("GENRE", "Rock")]);
+        rules.apply(&mut two);
+        assert_eq!(
+            pairs(&two),
+            pairs(&track(
+                2,
+                &[("TITLE", "Two"), ("GENRE", "Rock"), ("GENRE", "Pop")]
+            ))
+        );
+    }
+
+    #[test]
+    fn invalid_rules() {
+        assert!(TagRules::from_str("[[rule]]\nfrop = [\"COMMENT\"]").is_err());
+        assert!(TagRules::from_str("[[rule]]\nwhen_matches = { TITLE = \"(\" }").is_err());
+        assert!(TagRules::from_str("").unwrap().is_empty());
+    }
+}
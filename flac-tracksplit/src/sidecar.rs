@@ -0,0 +1,334 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use symphonia_core::meta::{Tag, Value};
+use tracing::info;
+
+use crate::{Error, Result, TrackTagConvention, track_tags::tag_scope};
+
+/// What to do with a sidecar tag file next to a disc image, which
+/// holds tags maintained outside of the image (the image itself is
+/// never changed). For a disc image `album.flac`, it is the first of
+/// these that exists:
+///
+/// * `album.tags`: comments as exported by `metaflac
+///   --export-tags-to`, one `KEY=value` per line (lines without a `=`
+///   continue the previous value), with per-track tags like
+///   `TITLE[3]=…`, or in any of the recognised [TrackTagConvention]s.
+/// * `album.tags.csv`: a table with a header row of keys, whose first
+///   column, `TRACK`, holds the track number each row's tags apply to
+///   (or nothing, for the whole album). Keys may repeat to give several
+///   values; empty cells are skipped.
+/// * `album.tags.json`: an object with an `album` object of keys and
+///   values, and a `tracks` object of such objects keyed by track
+///   number. Values are strings or arrays of strings.
+///
+/// Per-track tags of the form `TITLE[3]` are recognised in sidecar
+/// files even if [TrackTagConvention::Suffix] isn't otherwise.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SidecarTags {
+    /// Don't look for sidecar tag files.
+    #[default]
+    Ignore,
+
+    /// Add the sidecar file's tags to the disc image's: a key tagged
+    /// in the sidecar file, for the album or a track, replaces the
+    /// image's values of that key for the album or that track.
+    Overlay,
+
+    /// Use the sidecar file's tags instead of all of the disc image's.
+    Replace,
+}
+
+impl FromStr for SidecarTags {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(SidecarTags::Ignore),
+            "overlay" => Ok(SidecarTags::Overlay),
+            "replace" => Ok(SidecarTags::Replace),
+            _ => Err(Error::UnknownOption {
+                option: "sidecar tags mode",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for SidecarTags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SidecarTags::Ignore => "ignore",
+            SidecarTags::Overlay => "overlay",
+            SidecarTags::Replace => "replace",
+        })
+    }
+}
+
+/// The sidecar file name extensions, in the order they are looked
+/// for.
+const EXTENSIONS: [&str; 3] = ["tags", "tags.csv", "tags.json"];
+
+/// The sidecar tag file of the disc image at `input_path`, if there
+/// is one.
+fn sidecar_path(input_path: &Path) -> Option<PathBuf> {
+    let stem = input_path.file_stem()?.to_string_lossy();
+    EXTENSIONS
+        .iter()
+        .map(|extension| input_path.with_file_name(format!("{}.{}", stem, extension)))
+        .find(|path| path.is_file())
+}
+
+fn string_tag(key: &str, value: impl Into<String>) -> Tag {
+    Tag::new(None, key, Value::String(value.into()))
+}
+
+/// The key of a tag for the track numbered `track`, or the whole
+/// album.
+fn scoped_key(key: &str, track: Option<u32>) -> String {
+    match track {
+        Some(track) => format!("{}[{}]", key, track),
+        None => key.to_string(),
+    }
+}
+
+fn parse_comments(contents: &str) -> Result<Vec<Tag>, String> {
+    let mut comments: Vec<(String, String)> = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        match line.split_once('=') {
+            Some((key, value)) if !key.is_empty() => {
+                comments.push((key.to_string(), value.to_string()))
+            }
+            _ => match comments.last_mut() {
+                Some((_, value)) => {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+                None if line.trim().is_empty() => {}
+                None => return Err(format!("line {} is not a KEY=value comment", i + 1)),
+            },
+        }
+    }
+    // Blank lines between comments don't belong to their values:
+    Ok(comments
+        .iter()
+        .map(|(key, value)| string_tag(key, value.trim_end_matches('\n')))
+        .collect())
+}
+
+fn parse_track(track: &str) -> Result<Option<u32>, String> {
+    let track = track.trim();
+    if track.is_empty() {
+        return Ok(None);
+    }
+    track
+        .parse()
+        .map(Some)
+        .map_err(|_| format!("{:?} is not a track number", track))
+}
+
+fn parse_csv(contents: &str) -> Result<Vec<Tag>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_reader(contents.as_bytes());
+    let keys = reader.headers().map_err(|err| err.to_string())?.clone();
+    if !keys
+        .get(0)
+        .is_some_and(|key| key.trim().eq_ignore_ascii_case("TRACK"))
+    {
+        return Err("the first column must be TRACK".to_string());
+    }
+    let mut tags = vec![];
+    for record in reader.records() {
+        let record = record.map_err(|err| err.to_string())?;
+        let track = parse_track(record.get(0).unwrap_or_default())?;
+        for (key, value) in keys.iter().zip(record.iter()).skip(1) {
+            if !value.is_empty() {
+                tags.push(string_tag(&scoped_key(key.trim(), track), value));
+            }
+        }
+    }
+    Ok(tags)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum JsonValues {
+    One(String),
+    Many(Vec<String>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonTags {
+    #[serde(default)]
+    album: BTreeMap<String, JsonValues>,
+    #[serde(default)]
+    tracks: BTreeMap<String, BTreeMap<String, JsonValues>>,
+}
+
+fn parse_json(contents: &str) -> Result<Vec<Tag>, String> {
+    let json: JsonTags = serde_json::from_str(contents).map_err(|err| err.to_string())?;
+    let mut tracks = json
+        .tracks
+        .into_iter()
+        .map(|(track, tags)| Ok((parse_track(&track)?, tags)))
+        .collect::<Result<Vec<_>, String>>()?;
+    tracks.sort_by_key(|(track, _)| *track);
+    let mut tags = vec![];
+    for (track, values) in std::iter::once((None, json.album)).chain(tracks) {
+        for (key, values) in values {
+            let values = match values {
+                JsonValues::One(value) => vec![value],
+                JsonValues::Many(values) => values,
+            };
+            let key = scoped_key(&key, track);
+            tags.extend(values.into_iter().map(|value| string_tag(&key, value)));
+        }
+    }
+    Ok(tags)
+}
+
+/// Read the tags of the sidecar file at `path`.
+fn read_sidecar(path: &Path) -> Result<Vec<Tag>> {
+    let contents = fs::read_to_string(path).map_err(|source| Error::InputIo {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let contents = contents.strip_prefix('\u{feff}').unwrap_or(&contents);
+    let name = path.to_string_lossy();
+    let parsed = if name.ends_with(".tags.csv") {
+        parse_csv(contents)
+    } else if name.ends_with(".tags.json") {
+        parse_json(contents)
+    } else {
+        parse_comments(contents)
+    };
+    parsed.map_err(|reason| Error::InvalidSidecar {
+        path: path.to_path_buf(),
+        reason,
+    })
+}
+
+impl SidecarTags {
+    /// The tags of the disc image at `input_path`, given its embedded
+    /// `tags`, with those of its sidecar file applied, and the
+    /// conventions in which their per-track keys are recognised: the
+    /// given `conventions`, plus [TrackTagConvention::Suffix] if there
+    /// is a sidecar file.
+    pub(crate) fn apply(
+        self,
+        input_path: &Path,
+        tags: &[Tag],
+        conventions: &[TrackTagConvention],
+    ) -> Result<(Vec<Tag>, Vec<TrackTagConvention>)> {
+        let unchanged = || Ok((tags.to_vec(), conventions.to_vec()));
+        if self == SidecarTags::Ignore {
+            return unchanged();
+        }
+        let Some(path) = sidecar_path(input_path) else {
+            return unchanged();
+        };
+        let sidecar = read_sidecar(&path)?;
+        info!(path = ?path, mode = %self, tags = sidecar.len(), "Using sidecar tags.");
+        // The sidecar file's per-track tags are always recognised in
+        // the `KEY[N]` form. The image's tags of that form would apply
+        // to no track if the conventions don't include it, so they
+        // are dropped rather than taken for the track's:
+        let mut tags = tags.to_vec();
+        let mut conventions = conventions.to_vec();
+        if !conventions.contains(&TrackTagConvention::Suffix) {
+            let suffixed = [TrackTagConvention::Suffix];
+            tags.retain(|tag| tag_scope(&tag.key, &suffixed).1.is_none());
+            conventions.push(TrackTagConvention::Suffix);
+        }
+        if self == SidecarTags::Replace {
+            return Ok((sidecar, conventions));
+        }
+        let scopes: Vec<_> = sidecar
+            .iter()
+            .map(|tag| {
+                let (key, track) = tag_scope(&tag.key, &conventions);
+                (key.to_string(), track)
+            })
+            .collect();
+        let overlaid = |tag: &Tag| {
+            let (key, track) = tag_scope(&tag.key, &conventions);
+            scopes.iter().any(|(other, other_track)| {
+                *other_track == track && other.eq_ignore_ascii_case(key)
+            })
+        };
+        let tags = tags
+            .iter()
+            .filter(|tag| !overlaid(tag))
+            .cloned()
+            .chain(sidecar)
+            .collect();
+        Ok((tags, conventions))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn pairs(tags: &[Tag]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|tag| (tag.key.clone(), tag.value.to_string()))
+            .collect()
+    }
+
+    fn expected(tags: &[(&str, &str)]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!(
+            pairs(&parse_comments("ALBUM=Album\nCOMMENT=Two\nlines\n\nTITLE[3]=x=y\n").unwrap()),
+            expected(&[
+                ("ALBUM", "Album"),
+                ("COMMENT", "Two\nlines"),
+                ("TITLE[3]", "x=y")
+            ])
+        );
+        assert_eq!(
+            pairs(
+                &parse_csv(
+                    "Track,ALBUM,TITLE,ARTIST,ARTIST\n,Album,,,\n1,,One,A,B\n02,,\"Two, too\",,\n"
+                )
+                .unwrap()
+            ),
+            expected(&[
+                ("ALBUM", "Album"),
+                ("TITLE[1]", "One"),
+                ("ARTIST[1]", "A"),
+                ("ARTIST[1]", "B"),
+                ("TITLE[2]", "Two, too"),
+            ])
+        );
+        assert!(parse_csv("TITLE\nOne\n").is_err());
+        assert_eq!(
+            pairs(
+                &parse_json(
+                    r#"{"album": {"ALBUM": "Album"},
+                        "tracks": {"10": {"TITLE": "Ten"}, "2": {"ARTIST": ["A", "B"]}}}"#
+                )
+                .unwrap()
+            ),
+            expected(&[
+                ("ALBUM", "Album"),
+                ("ARTIST[2]", "A"),
+                ("ARTIST[2]", "B"),
+                ("TITLE[10]", "Ten"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use metaflac::Block;
+use std::{
+    fs::{self, create_dir_all},
+    io::{self, Write},
+    path::Path,
+};
+use tempfile::NamedTempFile;
+use tracing::{info, warn};
+
+use crate::{ExistingPolicy, existing};
+
+/// Receives the tracks split out of a disc image.
+///
+/// For each planned track, [TrackSink::should_write] decides whether
+/// it gets written at all. If so, the track's FLAC data gets written
+/// front to back into the writer returned by [TrackSink::create],
+/// which is then handed to [TrackSink::finish].
+///
+/// Errors of kind [io::ErrorKind::AlreadyExists] are reported as
+/// [Error::OutputExists](crate::Error::OutputExists), all others as
+/// [Error::OutputIo](crate::Error::OutputIo).
+pub trait TrackSink {
+    /// Receives one track's FLAC data.
+    type Writer: Write;
+
+    /// Decide whether to write the track planned for `path`, which
+    /// consists of the metadata `blocks` (the last of which is
+    /// padding) and the `audio` frames. By default, every track gets written.
+    fn should_write(&mut self, _path: &Path, _blocks: &[Block], _audio: &[u8]) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Start writing the track planned for `path`.
+    fn create(&mut self, path: &Path) -> io::Result<Self::Writer>;
+
+    /// Complete the track planned for `path`, after all its data was
+    /// written to `writer`.
+    fn finish(&mut self, path: &Path, writer: Self::Writer) -> io::Result<()>;
+}
+
+/// Writes tracks to files at their planned paths, creating parent
+/// directories as needed.
+///
+/// Each track is written to a temporary file next to its destination,
+/// and only moved into place when complete, after which the directory
+/// is synced as well (failing which only logs a warning); if anything
+/// goes wrong before that, the temporary file is removed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilesystemSink {
+    existing: ExistingPolicy,
+}
+
+impl FilesystemSink {
+    /// A sink that handles files that already exist at a track's path
+    /// according to `existing`.
+    pub fn new(existing: ExistingPolicy) -> Self {
+        Self { existing }
+    }
+
+    /// Write a file that accompanies the tracks, such as a playlist,
+    /// to `path` the same way as tracks, and according to the same
+    /// policy for existing files.
+    ///
+    /// Returns whether the file was written.
+    pub(crate) fn write_file(&mut self, path: &Path, contents: &[u8]) -> io::Result<bool> {
+        if !self.should_write_file(path, contents) {
+            return Ok(false);
+        }
+        let mut f = self.create(path)?;
+        f.write_all(contents)?;
+        self.finish(path, f)?;
+        Ok(true)
+    }
+
+    /// Decide whether to write `contents` to the file at `path`,
+    /// according to the policy for existing files.
+    pub(crate) fn should_write_file(&self, path: &Path, contents: &[u8]) -> bool {
+        match self.existing {
+            ExistingPolicy::Skip if path.exists() => {
+                info!(?path, "Output exists, skipping.");
+                false
+            }
+            ExistingPolicy::IfChanged if fs::read(path).is_ok_and(|old| old == contents) => {
+                info!(?path, "Output is unchanged, skipping.");
+                false
+            }
+            _ => true,
+        }
+    }
+}
+
+impl TrackSink for FilesystemSink {
+    type Writer = NamedTempFile;
+
+    fn should_write(&mut self, path: &Path, blocks: &[Block], audio: &[u8]) -> io::Result<bool> {
+        match self.existing {
+            ExistingPolicy::Skip if path.exists() => {
+                info!(?path, "Output exists, skipping.");
+                Ok(false)
+            }
+            ExistingPolicy::IfChanged if existing::is_unchanged(path, blocks, audio)? => {
+                info!(?path, "Output is unchanged, skipping.");
+                Ok(false)
+            }
+            _ => Ok(true),
+        }
+    }
+
+    fn create(&mut self, path: &Path) -> io::Result<NamedTempFile> {
+        let parent = parent_dir(path);
+        create_dir_all(parent)?;
+        tempfile::Builder::new()
+            .prefix(".flac-tracksplit-")
+            .suffix(".tmp")
+            .tempfile_in(parent)
+    }
+
+    fn finish(&mut self, path: &Path, f: NamedTempFile) -> io::Result<()> {
+        f.as_file().sync_all()?;
+        if self.existing == ExistingPolicy::Fail {
+            f.persist_noclobber(path)
+        } else {
+            f.persist(path)
+        }
+        .map_err(|e| e.error)?;
+        // The track is in place now, so failing to sync its directory
+        // must not fail the track, or it would not be cleaned up with
+        // the others written from a failed split:
+        let dir = parent_dir(path);
+        if let Err(error) = sync_dir(dir) {
+            warn!(?dir, %error, "Could not sync album directory.");
+        }
+        Ok(())
+    }
+}
+
+/// The directory that `path` is in.
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Flush the directory entries of `dir` to disk, so that files just
+/// moved into it stay there after a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened for syncing on this platform; renames
+/// are left to the filesystem.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
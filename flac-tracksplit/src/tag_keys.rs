@@ -0,0 +1,107 @@
+use symphonia_core::meta::Tag;
+use tracing::warn;
+
+/// Keys that name the same thing as a more common key, which
+/// normalized tags use instead.
+const KEY_ALIASES: [(&str, &str); 4] = [
+    ("TOTALDISCS", "DISCTOTAL"),
+    ("TOTALTRACKS", "TRACKTOTAL"),
+    ("YEAR", "DATE"),
+    ("ALBUM ARTIST", "ALBUMARTIST"),
+];
+
+/// The key that normalized tags use for `key`: uppercased, and with
+/// aliases replaced by their common key.
+fn normalized_key(key: &str) -> String {
+    let key = key.to_ascii_uppercase();
+    KEY_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .map_or(key, |(_, common)| common.to_string())
+}
+
+/// Uppercase the keys of `tags` and replace aliases by their common
+/// key, see [SplitOptions::normalize_tags](crate::SplitOptions::normalize_tags).
+///
+/// Values of an alias are dropped if its common key is tagged already
+/// (with a warning, also added to `warnings`, if they differ), so that
+/// e.g. a `TOTALTRACKS` that duplicates `TRACKTOTAL` doesn't turn into
+/// a second value of it.
+pub(crate) fn normalize_tags(track: u32, tags: &mut Vec<Tag>, warnings: &mut Vec<String>) {
+    let tagged: Vec<(String, String)> = tags
+        .iter()
+        .map(|tag| (tag.key.to_ascii_uppercase(), tag.value.to_string()))
+        .filter(|(key, _)| !KEY_ALIASES.iter().any(|(alias, _)| alias == key))
+        .collect();
+    let mut normalized = Vec::with_capacity(tags.len());
+    for tag in tags.drain(..) {
+        let key = normalized_key(&tag.key);
+        let mut common_values = tagged
+            .iter()
+            .filter(|(common, _)| *common == key)
+            .map(|(_, value)| value)
+            .peekable();
+        if !tag.key.eq_ignore_ascii_case(&key) && common_values.peek().is_some() {
+            let value = tag.value.to_string();
+            if !common_values.any(|common| *common == value) {
+                warn!(
+                    track,
+                    key = tag.key,
+                    value,
+                    common_key = key,
+                    "Dropping tag whose value disagrees with its common key's."
+                );
+                warnings.push(format!(
+                    "dropped {}={}, which disagrees with {}",
+                    tag.key, value, key
+                ));
+            }
+            continue;
+        }
+        normalized.push(Tag::new(tag.std_key, &key, tag.value));
+    }
+    *tags = normalized;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use symphonia_core::meta::Value;
+
+    fn tags(tags: &[(&str, &str)]) -> Vec<Tag> {
+        tags.iter()
+            .map(|(key, value)| Tag::new(None, key, Value::String(value.to_string())))
+            .collect()
+    }
+
+    fn pairs(tags: &[Tag]) -> Vec<(String, String)> {
+        tags.iter()
+            .map(|tag| (tag.key.clone(), tag.value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn normalizing() {
+        let mut normalized = tags(&[
+            ("Album Artist", "Someone"),
+            ("album", "Album"),
+            ("Year", "2001"),
+            ("TrackTotal", "12"),
+            ("TOTALTRACKS", "12"),
+            ("totaldiscs", "2"),
+        ]);
+        let mut warnings = vec![];
+        normalize_tags(1, &mut normalized, &mut warnings);
+        assert_eq!(
+            pairs(&normalized),
+            pairs(&tags(&[
+                ("ALBUMARTIST", "Someone"),
+                ("ALBUM", "Album"),
+                ("DATE", "2001"),
+                ("TRACKTOTAL", "12"),
+                ("DISCTOTAL", "2"),
+            ]))
+        );
+        assert_eq!(warnings, Vec::<String>::new());
+    }
+}
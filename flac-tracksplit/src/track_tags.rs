@@ -0,0 +1,290 @@
+use std::{fmt, str::FromStr};
+use symphonia_core::meta::Tag;
+
+use crate::Error;
+
+/// A convention for tagging the tracks of a disc image individually,
+/// by adding the track number to a tag's key. Track numbers may be
+/// zero-padded in all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrackTagConvention {
+    /// `TITLE[3]` or `TITLE[03]`.
+    Suffix,
+
+    /// `TITLE[1.3]`: track 3 of disc 1. Only tags of the disc image's
+    /// `DISCNUMBER` apply, or those of any disc if it isn't tagged.
+    DiscSuffix,
+
+    /// `CUE_TRACK03_TITLE`.
+    CueTrack,
+
+    /// `TRACK03TITLE`.
+    TrackPrefix,
+}
+
+impl TrackTagConvention {
+    /// All conventions, which [SplitOptions](crate::SplitOptions)
+    /// recognise by default.
+    pub const ALL: [TrackTagConvention; 4] = [
+        TrackTagConvention::Suffix,
+        TrackTagConvention::DiscSuffix,
+        TrackTagConvention::CueTrack,
+        TrackTagConvention::TrackPrefix,
+    ];
+
+    /// Split a `key` following this convention into the tag's key,
+    /// disc (if it names one) and track number.
+    fn parse(self, key: &str) -> Option<TrackKey<'_>> {
+        match self {
+            TrackTagConvention::Suffix | TrackTagConvention::DiscSuffix => {
+                let (key, suffix) = key.strip_suffix(']')?.rsplit_once('[')?;
+                let (disc, track) = match (self, suffix.split_once('.')) {
+                    (TrackTagConvention::Suffix, None) => (None, suffix),
+                    (TrackTagConvention::DiscSuffix, Some((disc, track))) => {
+                        (Some(number(disc)?), track)
+                    }
+                    _ => return None,
+                };
+                (!key.is_empty()).then_some(TrackKey {
+                    key,
+                    disc,
+                    track: number(track)?,
+                })
+            }
+            TrackTagConvention::CueTrack => {
+                let rest = strip_prefix_ignore_case(key, "CUE_TRACK")?;
+                let (track, key) = rest.split_once('_')?;
+                (!key.is_empty()).then_some(TrackKey {
+                    key,
+                    disc: None,
+                    track: number(track)?,
+                })
+            }
+            TrackTagConvention::TrackPrefix => {
+                let rest = strip_prefix_ignore_case(key, "TRACK")?;
+                let digits =
+                    rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let (track, key) = rest.split_at(digits);
+                key.starts_with(|c: char| c.is_ascii_alphabetic())
+                    .then_some(TrackKey {
+                        key,
+                        disc: None,
+                        track: number(track)?,
+                    })
+            }
+        }
+    }
+}
+
+impl FromStr for TrackTagConvention {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "suffix" => Ok(TrackTagConvention::Suffix),
+            "disc-suffix" => Ok(TrackTagConvention::DiscSuffix),
+            "cue-track" => Ok(TrackTagConvention::CueTrack),
+            "track-prefix" => Ok(TrackTagConvention::TrackPrefix),
+            _ => Err(Error::UnknownOption {
+                option: "track tag convention",
+                value: s.to_string(),
+            }),
+        }
+    }
+}
+
+impl fmt::Display for TrackTagConvention {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TrackTagConvention::Suffix => "suffix",
+            TrackTagConvention::DiscSuffix => "disc-suffix",
+            TrackTagConvention::CueTrack => "cue-track",
+            TrackTagConvention::TrackPrefix => "track-prefix",
+        })
+    }
+}
+
+/// The parts of a key that applies to a single track.
+#[derive(Debug, PartialEq, Eq)]
+struct TrackKey<'a> {
+    key: &'a str,
+    disc: Option<u32>,
+    track: u32,
+}
+
+/// A (possibly zero-padded) number, which can't have a sign.
+fn number(digits: &str) -> Option<u32> {
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+fn strip_prefix_ignore_case<'a>(s: &'a str, prefix: &str) -> Option<&'a str> {
+    s.get(..prefix.len())
+        .filter(|start| start.eq_ignore_ascii_case(prefix))
+        .map(|_| &s[prefix.len()..])
+}
+
+fn track_key<'a>(conventions: &[TrackTagConvention], key: &'a str) -> Option<TrackKey<'a>> {
+    conventions
+        .iter()
+        .find_map(|convention| convention.parse(key))
+}
+
+/// Split a disc image's tag `key` into the key it has in the tracks it
+/// applies to, and the number of the track, if it applies to one only
+/// in one of the `conventions`.
+pub(crate) fn tag_scope<'a>(
+    key: &'a str,
+    conventions: &[TrackTagConvention],
+) -> (&'a str, Option<u32>) {
+    match track_key(conventions, key) {
+        Some(key) => (key.key, Some(key.track)),
+        None => (key, None),
+    }
+}
+
+/// Whether any of the disc image's `tags` apply to the track numbered
+/// `track` only, in one of the `conventions`.
+pub(crate) fn has_track_tags(tags: &[Tag], conventions: &[TrackTagConvention], track: u32) -> bool {
+    tags.iter()
+        .any(|tag| track_key(conventions, &tag.key).is_some_and(|key| key.track == track))
+}
+
+/// Select the tags of CUE sheet track `track` from a disc image's
+/// `tags`: the album-level ones, and those that apply to the track
+/// only in one of the `conventions` (with the track number removed
+/// from their key), in order.
+///
+/// If a key has any values for the track only, the track takes only
+/// those: they replace the album-level values rather than adding to
+/// them (keys are compared ignoring case, as vorbis comment field
+/// names are case-insensitive). Keys tagged more than once keep all
+/// of their values. Tags that apply to other tracks, and the
+/// `CUESHEET` and `LOG` tags, are dropped.
+pub fn select_track_tags(tags: &[Tag], conventions: &[TrackTagConvention], track: u32) -> Vec<Tag> {
+    let parsed: Vec<_> = tags
+        .iter()
+        .map(|tag| track_key(conventions, &tag.key))
+        .collect();
+    let disc = tags
+        .iter()
+        .zip(&parsed)
+        .find(|(tag, parsed)| parsed.is_none() && tag.key.eq_ignore_ascii_case("DISCNUMBER"))
+        .and_then(|(tag, _)| {
+            let value = tag.value.to_string();
+            let disc = value
+                .split_once('/')
+                .map_or(value.as_str(), |(disc, _)| disc);
+            number(disc.trim())
+        });
+    let applies = |key: &TrackKey| {
+        key.track == track && (key.disc.is_none() || disc.is_none() || key.disc == disc)
+    };
+    let replaced: Vec<&str> = parsed
+        .iter()
+        .flatten()
+        .filter(|key| applies(key))
+        .map(|key| key.key)
+        .collect();
+    tags.iter()
+        .zip(&parsed)
+        .filter_map(|(tag, parsed)| {
+            let key = match parsed {
+                Some(key) if applies(key) => key.key,
+                Some(_) => return None,
+                None if album_key(&tag.key)
+                    && !replaced
+                        .iter()
+                        .any(|key| key.eq_ignore_ascii_case(&tag.key)) =>
+                {
+                    &tag.key
+                }
+                None => return None,
+            };
+            Some(Tag::new(tag.std_key, key, tag.value.clone()))
+        })
+        .collect()
+}
+
+/// Whether a disc image's tag `key` applies to all of its tracks.
+pub(crate) fn is_album_tag(key: &str, conventions: &[TrackTagConvention]) -> bool {
+    track_key(conventions, key).is_none() && album_key(key)
+}
+
+/// Whether a disc image's tag `key`, which doesn't apply to a single
+/// track in any of the recognised conventions, applies to all tracks.
+fn album_key(key: &str) -> bool {
+    !key.ends_with(']') && !key.eq_ignore_ascii_case("CUESHEET") && !key.eq_ignore_ascii_case("LOG")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use symphonia_core::meta::Value;
+
+    #[test]
+    fn track_keys() {
+        let parse = |key| track_key(&TrackTagConvention::ALL, key);
+        let track = |key, disc, track| Some(TrackKey { key, disc, track });
+        assert_eq!(parse("TITLE[3]"), track("TITLE", None, 3));
+        assert_eq!(parse("TITLE[03]"), track("TITLE", None, 3));
+        assert_eq!(parse("TITLE[1.03]"), track("TITLE", Some(1), 3));
+        assert_eq!(parse("CUE_TRACK03_TITLE"), track("TITLE", None, 3));
+        assert_eq!(
+            parse("cue_track3_ALBUM ARTIST"),
+            track("ALBUM ARTIST", None, 3)
+        );
+        assert_eq!(parse("TRACK03TITLE"), track("TITLE", None, 3));
+        assert_eq!(parse("TITLE"), None);
+        assert_eq!(parse("TRACKNUMBER"), None);
+        assert_eq!(parse("TRACKTOTAL"), None);
+        assert_eq!(parse("TRACK03"), None);
+        assert_eq!(parse("TITLE[]"), None);
+        assert_eq!(parse("TITLE[+3]"), None);
+        assert_eq!(parse("[3]"), None);
+        assert_eq!(
+            TrackTagConvention::Suffix.parse("TITLE[1.3]"),
+            None,
+            "disc suffixes are a convention of their own"
+        );
+    }
+
+    #[test]
+    fn selecting() {
+        let tags: Vec<Tag> = [
+            ("ALBUM", "Album"),
+            ("DISCNUMBER", "2/2"),
+            ("TITLE[02]", "Suffixed"),
+            ("TITLE[1.2]", "Other disc"),
+            ("ARTIST[2.2]", "Disc suffixed"),
+            ("CUE_TRACK02_GENRE", "Rock"),
+            ("TRACK2COMMENT", "Hi"),
+            ("TRACK3COMMENT", "Other track"),
+        ]
+        .iter()
+        .map(|(key, value)| Tag::new(None, key, Value::String(value.to_string())))
+        .collect();
+        let selected: Vec<_> = select_track_tags(&tags, &TrackTagConvention::ALL, 2)
+            .iter()
+            .map(|tag| (tag.key.clone(), tag.value.to_string()))
+            .collect();
+        assert_eq!(
+            selected,
+            [
+                ("ALBUM", "Album"),
+                ("DISCNUMBER", "2/2"),
+                ("TITLE", "Suffixed"),
+                ("ARTIST", "Disc suffixed"),
+                ("GENRE", "Rock"),
+                ("COMMENT", "Hi"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+
+        let selected = select_track_tags(&tags, &[TrackTagConvention::Suffix], 2);
+        assert!(!selected.iter().any(|tag| tag.key == "GENRE"));
+        assert!(selected.iter().any(|tag| tag.key == "CUE_TRACK02_GENRE"));
+    }
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::{Image, files};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+fn run(dir: &Path, args: &[&str]) -> Option<i32> {
+    Command::new(env!("CARGO_BIN_EXE_flac-tracksplit"))
+        .current_dir(dir)
+        .args(["--output-dir", "out"])
+        .args(args)
+        .output()
+        .unwrap()
+        .status
+        .code()
+}
+
+#[test]
+fn keep_going_exit_codes() {
+    let dir = tempfile::tempdir().unwrap();
+    Image::new(4, 2).write(dir.path().join("good.flac"));
+    Image {
+        cue_sheet: false,
+        ..Image::new(4, 2)
+    }
+    .write(dir.path().join("no-cue.flac"));
+    fs::write(dir.path().join("broken.flac"), "not a FLAC file").unwrap();
+
+    assert_eq!(run(dir.path(), &["--keep-going", "good.flac"]), Some(0));
+    assert_eq!(
+        run(dir.path(), &["--keep-going", "good.flac", "no-cue.flac"]),
+        Some(3)
+    );
+    assert_eq!(
+        run(
+            dir.path(),
+            &["--keep-going", "broken.flac", "no-cue.flac", "good.flac"]
+        ),
+        Some(1)
+    );
+    assert_eq!(files(dir.path().join("out")).len(), 2);
+}
+
+#[test]
+fn failures_stop_without_keep_going() {
+    let dir = tempfile::tempdir().unwrap();
+    fs::write(dir.path().join("broken.flac"), "not a FLAC file").unwrap();
+    assert_ne!(run(dir.path(), &["broken.flac"]), Some(0));
+}
+
+#[test]
+fn applying_a_written_plan() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = Image::new(4, 2).tags(&["TITLE[1]=One", "TITLE[2]=Two"]);
+    image.tracks[0].2 = vec![0, 5880];
+    image.write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let split = |args: &[&str]| {
+        assert_eq!(run(dir.path(), args), Some(0));
+        let split: Vec<_> = files(&out)
+            .into_iter()
+            .map(|path| (fs::read(out.join(&path)).unwrap(), path))
+            .collect();
+        fs::remove_dir_all(&out).unwrap();
+        split
+    };
+
+    let expected = split(&["--provenance", "image.flac"]);
+    assert_eq!(expected.len(), 3);
+    assert_eq!(
+        run(dir.path(), &["--write-plan", "plan.json", "image.flac"]),
+        Some(0)
+    );
+    assert!(!out.exists());
+    // The pregap's CUE sheet track is known without provenance in
+    // the plan file:
+    assert_eq!(
+        split(&["--apply-plan", "plan.json", "--provenance"]),
+        expected
+    );
+
+    // Options picking tracks, paths, tags and pictures are rejected:
+    for option in [
+        &["--tag-rules", "rules.toml"][..],
+        &["--pictures", "none"],
+        &["--normalize-tags"],
+        &["--naming", "album"],
+        &["--pregap", "drop"],
+    ] {
+        let args = [&["--apply-plan", "plan.json"], option].concat();
+        assert_eq!(run(dir.path(), &args), Some(2));
+    }
+}
+
+#[test]
+fn naming_and_pregaps() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = Image::new(4, 2).tags(&["ARTIST=Artist", "ALBUM=Album"]);
+    image.tracks[0].2 = vec![0, 5880];
+    image.write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let split = |args: &[&str]| {
+        assert_eq!(run(dir.path(), args), Some(0));
+        let split = files(&out);
+        fs::remove_dir_all(&out).unwrap();
+        split
+    };
+
+    assert_eq!(
+        split(&["image.flac"]),
+        [
+            "Artist/Album/00.flac",
+            "Artist/Album/01.flac",
+            "Artist/Album/02.flac"
+        ]
+        .map(PathBuf::from)
+    );
+    assert_eq!(
+        split(&["--naming", "album", "--pregap", "prepend", "image.flac"]),
+        ["Album/01.flac", "Album/02.flac"].map(PathBuf::from)
+    );
+    assert_eq!(
+        run(dir.path(), &["--naming", "flat", "image.flac"]),
+        Some(2)
+    );
+}
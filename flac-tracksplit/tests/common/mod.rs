@@ -0,0 +1,334 @@
+//! Small FLAC disc images for the integration tests, written with
+//! verbatim subframes so that no encoder is needed.
+
+#![allow(dead_code)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+pub const SAMPLE_RATE: u64 = 44100;
+pub const BLOCK_SIZE: u64 = 4096;
+
+/// A disc image to write: 16 bit stereo at 44.1kHz, in frames of
+/// [BLOCK_SIZE] samples.
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub samples: u64,
+    /// Each CUE sheet track's number, start offset and index offsets
+    /// (relative to the track's start).
+    pub tracks: Vec<(u8, u64, Vec<u64>)>,
+    /// Whether the CUE sheet ends with a lead-out track.
+    pub lead_out: bool,
+    /// Whether the image has a CUE sheet at all.
+    pub cue_sheet: bool,
+    /// Whether STREAMINFO records the total number of samples.
+    pub total_samples_known: bool,
+    pub catalog: String,
+    pub tags: Vec<String>,
+    /// The contents of each PICTURE block.
+    pub pictures: Vec<Vec<u8>>,
+}
+
+impl Image {
+    /// An image of `seconds` length with one track starting every
+    /// `seconds / tracks` seconds.
+    pub fn new(seconds: u64, tracks: u8) -> Self {
+        let samples = seconds * SAMPLE_RATE;
+        Image {
+            samples,
+            tracks: (0..tracks)
+                .map(|i| (i + 1, samples * u64::from(i) / u64::from(tracks), vec![0]))
+                .collect(),
+            lead_out: true,
+            cue_sheet: true,
+            total_samples_known: true,
+            catalog: "1234567890123".to_string(),
+            tags: vec![],
+            pictures: vec![],
+        }
+    }
+
+    pub fn tags(mut self, tags: &[&str]) -> Self {
+        self.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    pub fn picture(mut self, picture_type: u32, mime_type: &str, data: &[u8]) -> Self {
+        let mut block = picture_type.to_be_bytes().to_vec();
+        block.extend((mime_type.len() as u32).to_be_bytes());
+        block.extend(mime_type.as_bytes());
+        block.extend(0u32.to_be_bytes());
+        for field in [1u32, 1, 24, 0, data.len() as u32] {
+            block.extend(field.to_be_bytes());
+        }
+        block.extend(data);
+        self.pictures.push(block);
+        self
+    }
+
+    /// Write the image to `path`, returning `path`.
+    pub fn write(&self, path: impl AsRef<Path>) -> PathBuf {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()).unwrap();
+        path.to_path_buf()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let frames: Vec<Vec<u8>> = (0..self.samples)
+            .step_by(BLOCK_SIZE as usize)
+            .enumerate()
+            .map(|(number, start)| {
+                frame(number as u64, start, BLOCK_SIZE.min(self.samples - start))
+            })
+            .collect();
+
+        let mut streaminfo = vec![];
+        streaminfo.extend((BLOCK_SIZE as u16).to_be_bytes());
+        streaminfo.extend((BLOCK_SIZE as u16).to_be_bytes());
+        let min_frame = frames.iter().map(Vec::len).min().unwrap_or(0) as u32;
+        let max_frame = frames.iter().map(Vec::len).max().unwrap_or(0) as u32;
+        streaminfo.extend(&min_frame.to_be_bytes()[1..]);
+        streaminfo.extend(&max_frame.to_be_bytes()[1..]);
+        let total_samples = if self.total_samples_known {
+            self.samples
+        } else {
+            0
+        };
+        let packed = (SAMPLE_RATE << 44) | (1 << 41) | (15 << 36) | total_samples;
+        streaminfo.extend(packed.to_be_bytes());
+        streaminfo.extend([0; 16]);
+
+        let mut comments = vec![];
+        comments.extend(7u32.to_le_bytes());
+        comments.extend(b"fixture");
+        comments.extend((self.tags.len() as u32).to_le_bytes());
+        for tag in &self.tags {
+            comments.extend((tag.len() as u32).to_le_bytes());
+            comments.extend(tag.as_bytes());
+        }
+
+        let mut blocks = vec![(0, streaminfo), (4, comments)];
+        if self.cue_sheet {
+            blocks.push((5, self.cue_sheet_block()));
+        }
+        for picture in &self.pictures {
+            blocks.push((6, picture.clone()));
+        }
+
+        let mut out = b"fLaC".to_vec();
+        let last = blocks.len() - 1;
+        for (i, (block_type, data)) in blocks.into_iter().enumerate() {
+            out.push(if i == last { 0x80 } else { 0 } | block_type);
+            out.extend(&(data.len() as u32).to_be_bytes()[1..]);
+            out.extend(data);
+        }
+        out.extend(frames.concat());
+        out
+    }
+
+    fn cue_sheet_block(&self) -> Vec<u8> {
+        let mut block = self.catalog.as_bytes().to_vec();
+        block.resize(128, 0);
+        block.extend(0u64.to_be_bytes());
+        block.push(0x80); // CD-DA
+        block.extend([0; 258]);
+        block.push((self.tracks.len() + usize::from(self.lead_out)) as u8);
+        for (number, offset, indices) in &self.tracks {
+            block.extend(offset.to_be_bytes());
+            block.push(*number);
+            block.extend([0; 12 + 14]);
+            block.push(indices.len() as u8);
+            for (i, index) in indices.iter().enumerate() {
+                block.extend(index.to_be_bytes());
+                block.push(if indices.len() == 1 { 1 } else { i as u8 });
+                block.extend([0; 3]);
+            }
+        }
+        if self.lead_out {
+            block.extend(self.samples.to_be_bytes());
+            block.push(170);
+            block.extend([0; 12 + 14 + 1]);
+        }
+        block
+    }
+}
+
+/// The sample value of `channel` at `i`; deterministic noise.
+fn sample(i: u64, channel: u64) -> i16 {
+    let noise = (i.wrapping_mul(2654435761) >> (8 + channel * 4)) % 64;
+    ((i * (37 + channel * 16)) % 2000) as i16 - 1000 + noise as i16 - 32
+}
+
+fn frame(number: u64, start: u64, samples: u64) -> Vec<u8> {
+    let mut header = vec![0xff, 0xf8, 0x79, 0x18];
+    header.extend(utf8_number(number));
+    header.extend(((samples - 1) as u16).to_be_bytes());
+    header.push(crc8(&header));
+    let mut frame = header;
+    for channel in 0..2 {
+        frame.push(0x02); // verbatim subframe
+        for i in start..start + samples {
+            frame.extend(sample(i, channel).to_be_bytes());
+        }
+    }
+    let crc = crc16(&frame);
+    frame.extend(crc.to_be_bytes());
+    frame
+}
+
+fn utf8_number(n: u64) -> Vec<u8> {
+    if n < 0x80 {
+        return vec![n as u8];
+    }
+    let len = (2..7).find(|len| n < 1 << (5 * len + 1)).unwrap();
+    let mut out: Vec<u8> = (0..len - 1)
+        .map(|i| 0x80 | ((n >> (6 * i)) & 0x3f) as u8)
+        .collect();
+    out.push((0xff << (8 - len)) as u8 | (n >> (6 * (len - 1))) as u8);
+    out.reverse();
+    out
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |mut crc, byte| {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, byte| {
+        crc ^= u16::from(*byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// The metadata blocks of the FLAC file at `path`, as block type and
+/// contents, followed by its audio frames.
+pub fn read_flac(path: impl AsRef<Path>) -> (Vec<(u8, Vec<u8>)>, Vec<u8>) {
+    let data = fs::read(path).unwrap();
+    assert_eq!(&data[..4], b"fLaC");
+    let mut blocks = vec![];
+    let mut offset = 4;
+    loop {
+        let header = data[offset];
+        let len = u32::from_be_bytes([0, data[offset + 1], data[offset + 2], data[offset + 3]]);
+        let start = offset + 4;
+        offset = start + len as usize;
+        blocks.push((header & 0x7f, data[start..offset].to_vec()));
+        if header & 0x80 != 0 {
+            break;
+        }
+    }
+    (blocks, data[offset..].to_vec())
+}
+
+/// The total number of samples that the STREAMINFO block of the FLAC
+/// file at `path` records.
+pub fn total_samples(path: impl AsRef<Path>) -> u64 {
+    let (blocks, _) = read_flac(path);
+    let streaminfo = &blocks[0].1;
+    u64::from_be_bytes(streaminfo[10..18].try_into().unwrap()) & ((1 << 36) - 1)
+}
+
+/// The vendor string and comments of the FLAC file at `path`.
+pub fn read_comments(path: impl AsRef<Path>) -> (String, Vec<String>) {
+    let (blocks, _) = read_flac(path);
+    let (_, block) = blocks
+        .iter()
+        .find(|(block_type, _)| *block_type == 4)
+        .expect("has a VORBIS_COMMENT block");
+    let mut rest = &block[..];
+    let read_u32 = |rest: &mut &[u8]| {
+        let (n, tail) = rest.split_at(4);
+        *rest = tail;
+        u32::from_le_bytes(n.try_into().unwrap()) as usize
+    };
+    let string = |rest: &mut &[u8]| {
+        let len = read_u32(rest);
+        let (s, tail) = rest.split_at(len);
+        *rest = tail;
+        String::from_utf8(s.to_vec()).unwrap()
+    };
+    let vendor = string(&mut rest);
+    let count = read_u32(&mut rest);
+    let comments = (0..count).map(|_| string(&mut rest)).collect();
+    (vendor, comments)
+}
+
+/// The values of tag `key` in the FLAC file at `path`.
+pub fn tag_values(path: impl AsRef<Path>, key: &str) -> Vec<String> {
+    read_comments(path)
+        .1
+        .into_iter()
+        .filter_map(|comment| {
+            let (k, v) = comment.split_once('=')?;
+            k.eq_ignore_ascii_case(key).then(|| v.to_string())
+        })
+        .collect()
+}
+
+/// The subframes of each audio frame of the FLAC file at `path`,
+/// without the frame headers (whose frame numbers change on
+/// splitting).
+pub fn frame_bodies(path: impl AsRef<Path>) -> Vec<Vec<u8>> {
+    let (_, audio) = read_flac(path);
+    let mut offset = 0;
+    let mut bodies = vec![];
+    while offset < audio.len() {
+        let number_len = match audio[offset + 4] {
+            b if b < 0x80 => 1,
+            b => b.leading_ones() as usize,
+        };
+        let size_at = offset + 4 + number_len;
+        let samples = usize::from(u16::from_be_bytes([audio[size_at], audio[size_at + 1]])) + 1;
+        let body_at = size_at + 3;
+        let end = body_at + 2 * (1 + 2 * samples);
+        bodies.push(audio[body_at..end].to_vec());
+        offset = end + 2;
+    }
+    bodies
+}
+
+/// The number of samples in each audio frame of the FLAC file at
+/// `path`.
+pub fn frame_samples(path: impl AsRef<Path>) -> Vec<u64> {
+    frame_bodies(path)
+        .iter()
+        .map(|body| (body.len() as u64 - 2) / 4)
+        .collect()
+}
+
+/// All files below `dir`, relative to it, in order.
+pub fn files(dir: impl AsRef<Path>) -> Vec<PathBuf> {
+    fn walk(base: &Path, dir: &Path, out: &mut Vec<PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                walk(base, &path, out);
+            } else {
+                out.push(path.strip_prefix(base).unwrap().to_path_buf());
+            }
+        }
+    }
+    let mut out = vec![];
+    walk(dir.as_ref(), dir.as_ref(), &mut out);
+    out.sort();
+    out
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{Image, files};
+use flac_tracksplit::{Error, ExistingPolicy, PlaylistFormat, SplitOptions};
+use std::fs;
+
+#[test]
+fn fail_writes_nothing_if_any_output_exists() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(6, 3).write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let plan = SplitOptions::new(&out)
+        .existing(ExistingPolicy::Fail)
+        .plan(&image)
+        .unwrap();
+    let last = &plan.tracks()[2].output_path;
+    fs::create_dir_all(last.parent().unwrap()).unwrap();
+    fs::write(last, "not a track").unwrap();
+
+    let error = plan.execute().unwrap_err();
+    assert!(
+        matches!(&error, Error::OutputExists { path } if path == last),
+        "{error:?}"
+    );
+    assert_eq!(files(&out).len(), 1);
+    assert_eq!(fs::read(last).unwrap(), b"not a track");
+}
+
+#[test]
+fn fail_checks_album_files_too() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(4, 2)
+        .picture(3, "image/jpeg", b"cover")
+        .write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let options = SplitOptions::new(&out)
+        .playlists([PlaylistFormat::M3u8])
+        .extract_pictures(true);
+    for path in options.split(&image).unwrap() {
+        fs::remove_file(path).unwrap();
+    }
+    let album_files = files(&out);
+    assert_eq!(album_files.len(), 2, "{album_files:?}");
+
+    let error = options
+        .existing(ExistingPolicy::Fail)
+        .split(&image)
+        .unwrap_err();
+    assert!(matches!(error, Error::OutputExists { .. }), "{error:?}");
+    assert_eq!(files(&out), album_files);
+}
+
+#[test]
+fn if_changed_leaves_unchanged_tracks_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(6, 3)
+        .tags(&["TITLE[2]=Two"])
+        .write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let options = SplitOptions::new(&out).existing(ExistingPolicy::IfChanged);
+    assert_eq!(options.split(&image).unwrap().len(), 3);
+    assert_eq!(options.split(&image).unwrap().len(), 0);
+
+    Image::new(6, 3)
+        .tags(&["TITLE[2]=Two", "TITLE[3]=Three"])
+        .write(&image);
+    let written = options.split(&image).unwrap();
+    assert_eq!(written.len(), 1);
+    assert!(written[0].ends_with("03.Three.flac"), "{written:?}");
+}
+
+#[test]
+fn if_changed_applies_new_padding() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(4, 2).write(dir.path().join("image.flac"));
+    let options = SplitOptions::new(dir.path().join("out")).existing(ExistingPolicy::IfChanged);
+    let paths = options.split(&image).unwrap();
+    let length = fs::metadata(&paths[0]).unwrap().len();
+
+    let padded = options.metadata_padding(8192);
+    assert_eq!(padded.split(&image).unwrap(), paths);
+    assert_ne!(fs::metadata(&paths[0]).unwrap().len(), length);
+    assert_eq!(padded.split(&image).unwrap().len(), 0);
+}
@@ -0,0 +1,43 @@
+mod common;
+
+use common::{Image, files, frame_bodies, read_comments, tag_values};
+use flac_tracksplit::{JoinOptions, SplitOptions};
+use std::fs;
+
+#[test]
+fn joined_tracks_split_into_the_same_tracks() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = Image::new(6, 3)
+        .tags(&[
+            "ALBUM=Album",
+            "ARTIST=Artist",
+            "TITLE[0]=Hidden",
+            "TITLE[1]=One",
+            "TITLE[2]=Two",
+            "ARTIST[2]=Guest",
+            "TITLE[3]=Three",
+        ])
+        .picture(3, "image/png", b"front")
+        .picture(4, "image/jpeg", b"back");
+    // Track 1 has a pregap of one second, which becomes track 0:
+    image.tracks[0].2 = vec![0, 44100];
+    let image = image.write(dir.path().join("image.flac"));
+    let split = dir.path().join("split");
+    let tracks = SplitOptions::new(&split).split(&image).unwrap();
+    assert_eq!(tracks.len(), 4);
+    assert_eq!(tag_values(&tracks[0], "TITLE"), ["Hidden"]);
+    assert_eq!(tag_values(&tracks[2], "ARTIST"), ["Guest"]);
+
+    let joined = dir.path().join("joined.flac");
+    assert!(JoinOptions::new().join(&tracks, &joined).unwrap());
+    assert_eq!(frame_bodies(&joined), frame_bodies(&image));
+
+    let resplit = dir.path().join("resplit");
+    let retracks = SplitOptions::new(&resplit).split(&joined).unwrap();
+    assert_eq!(files(&resplit), files(&split));
+    for (track, retrack) in tracks.iter().zip(&retracks) {
+        assert_eq!(frame_bodies(retrack), frame_bodies(track), "{retrack:?}");
+        assert_eq!(read_comments(retrack), read_comments(track), "{retrack:?}");
+        assert_eq!(fs::read(retrack).unwrap(), fs::read(track).unwrap());
+    }
+}
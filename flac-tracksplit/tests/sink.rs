@@ -0,0 +1,47 @@
+mod common;
+
+use common::Image;
+use flac_tracksplit::{SplitOptions, TrackSink};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Keeps the tracks in memory.
+#[derive(Default)]
+struct MemorySink {
+    tracks: Vec<(PathBuf, Vec<u8>)>,
+}
+
+impl TrackSink for MemorySink {
+    type Writer = Vec<u8>;
+
+    fn create(&mut self, _path: &Path) -> io::Result<Vec<u8>> {
+        Ok(vec![])
+    }
+
+    fn finish(&mut self, path: &Path, writer: Vec<u8>) -> io::Result<()> {
+        self.tracks.push((path.to_path_buf(), writer));
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_sinks_get_the_same_tracks_as_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(6, 3)
+        .tags(&["ALBUM=Album", "TITLE[1]=One"])
+        .picture(3, "image/png", b"not really a png")
+        .write(dir.path().join("image.flac"));
+    let plan = SplitOptions::new(dir.path().join("out"))
+        .plan(&image)
+        .unwrap();
+
+    let mut sink = MemorySink::default();
+    let paths = plan.execute_into(&mut sink).unwrap();
+    assert_eq!(paths, plan.execute().unwrap());
+    assert_eq!(sink.tracks.len(), 3);
+    for (path, contents) in &sink.tracks {
+        assert_eq!(&fs::read(path).unwrap(), contents, "{path:?}");
+    }
+}
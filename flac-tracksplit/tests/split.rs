@@ -0,0 +1,302 @@
+mod common;
+
+use common::{
+    BLOCK_SIZE, Image, SAMPLE_RATE, files, frame_bodies, frame_samples, tag_values, total_samples,
+};
+use flac_tracksplit::{
+    END_OF_STREAM_TS, Error, Interrupt, PathLimits, PregapPolicy, Result, SidecarTags,
+    SplitOptions, TagEntry, TrackStatus, TrackTagConvention, split_one_file,
+};
+use std::{fs, path::PathBuf};
+
+#[test]
+fn every_frame_goes_to_one_track() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(6, 3)
+        .tags(&["ARTIST=Someone", "ALBUM=Something", "TITLE[2]=Second"])
+        .write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+
+    let paths = SplitOptions::new(&out).split(&image).unwrap();
+    assert_eq!(paths.len(), 3);
+    let split_frames: Vec<_> = paths.iter().flat_map(frame_bodies).collect();
+    assert_eq!(split_frames, frame_bodies(&image));
+    assert_eq!(tag_values(&paths[1], "TITLE"), ["Second"]);
+    assert_eq!(tag_values(&paths[1], "ARTIST"), ["Someone"]);
+}
+
+#[test]
+fn failed_splits_leave_no_temporary_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut bytes = Image::new(6, 3).to_bytes();
+    bytes.truncate(bytes.len() - 100_000);
+    let image = dir.path().join("image.flac");
+    fs::write(&image, bytes).unwrap();
+    let out = dir.path().join("out");
+
+    let error = SplitOptions::new(&out).split(&image).unwrap_err();
+    assert!(
+        matches!(error, Error::ReadFrame { track: 3, .. }),
+        "{error:?}"
+    );
+    let written = files(&out);
+    assert_eq!(written.len(), 2, "{written:?}");
+    assert!(
+        written
+            .iter()
+            .all(|path| path.extension().unwrap() == "flac")
+    );
+
+    // Tracks left over from the first attempt would be kept:
+    fs::remove_dir_all(&out).unwrap();
+    SplitOptions::new(&out)
+        .remove_partial(true)
+        .split(&image)
+        .unwrap_err();
+    assert_eq!(files(&out), Vec::<PathBuf>::new());
+}
+
+#[test]
+fn removing_partial_splits_keeps_existing_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut bytes = Image::new(6, 3).to_bytes();
+    bytes.truncate(bytes.len() - 100_000);
+    let image = dir.path().join("image.flac");
+    fs::write(&image, bytes).unwrap();
+    let out = dir.path().join("out");
+    let plan = SplitOptions::new(&out)
+        .remove_partial(true)
+        .plan(&image)
+        .unwrap();
+    let existing = plan.tracks()[0].output_path.clone();
+    fs::create_dir_all(existing.parent().unwrap()).unwrap();
+    fs::write(&existing, b"from an earlier run").unwrap();
+
+    let mut report = plan.report();
+    let error = plan.execute_with_report(&mut report).unwrap_err();
+    assert!(
+        matches!(error, Error::ReadFrame { track: 3, .. }),
+        "{error:?}"
+    );
+    assert_eq!(files(&out), [existing.strip_prefix(&out).unwrap()]);
+    let statuses: Vec<_> = report.tracks.iter().map(|track| track.status).collect();
+    assert_eq!(
+        statuses,
+        [
+            TrackStatus::Written,
+            TrackStatus::Removed,
+            TrackStatus::Planned
+        ]
+    );
+}
+
+#[test]
+fn interrupting_stops_only_its_own_splits() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(4, 2).write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let interrupt = Interrupt::new();
+    let interrupted = SplitOptions::new(&out).interrupt(interrupt.clone());
+
+    interrupt.interrupt();
+    let error = interrupted.split(&image).unwrap_err();
+    assert!(matches!(error, Error::Interrupted), "{error:?}");
+    assert!(!out.exists());
+
+    let paths = SplitOptions::new(&out).split(&image).unwrap();
+    assert_eq!(paths.len(), 2);
+}
+
+#[test]
+fn pregap_policies() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = Image::new(6, 3);
+    // Track 1 has a pregap of one second:
+    image.tracks[0].2 = vec![0, SAMPLE_RATE];
+    let image = image.write(dir.path().join("image.flac"));
+    let split = |pregap: PregapPolicy| {
+        let out = dir.path().join(pregap.to_string());
+        let plan = SplitOptions::new(&out).pregap(pregap).plan(&image).unwrap();
+        let numbers: Vec<_> = plan.tracks().iter().map(|t| t.track.number).collect();
+        let paths = plan.execute().unwrap();
+        let frames: Vec<_> = paths.iter().map(frame_bodies).collect();
+        (numbers, frames)
+    };
+
+    let (numbers, split_frames) = split(PregapPolicy::Split);
+    assert_eq!(numbers, [0, 1, 2, 3]);
+    assert_eq!(split_frames.concat(), frame_bodies(&image));
+
+    let (numbers, prepended) = split(PregapPolicy::Prepend);
+    assert_eq!(numbers, [1, 2, 3]);
+    assert_eq!(prepended[0], split_frames[..2].concat());
+    assert_eq!(prepended[1..], split_frames[2..]);
+
+    let (numbers, dropped) = split(PregapPolicy::Drop);
+    assert_eq!(numbers, [1, 2, 3]);
+    assert_eq!(dropped, split_frames[1..]);
+}
+
+#[test]
+fn split_one_file_keeps_its_signature() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(4, 2).write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+    let split: fn(PathBuf, PathBuf, u32) -> Result<Vec<PathBuf>> = split_one_file;
+
+    let paths = split(image.clone(), out.clone(), 1024).unwrap();
+    assert_eq!(
+        paths,
+        SplitOptions::new(&out)
+            .plan(&image)
+            .unwrap()
+            .execute()
+            .unwrap()
+    );
+}
+
+#[test]
+fn streams_of_unknown_length() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image {
+        total_samples_known: false,
+        lead_out: false,
+        ..Image::new(6, 3)
+    }
+    .write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+
+    let plan = SplitOptions::new(&out).plan(&image).unwrap();
+    assert_eq!(plan.tracks()[2].track.end_ts, END_OF_STREAM_TS);
+    let paths = plan.execute().unwrap();
+    let split_frames: Vec<_> = paths.iter().flat_map(frame_bodies).collect();
+    assert_eq!(split_frames, frame_bodies(&image));
+    let samples: u64 = frame_samples(&paths[2]).iter().sum();
+    assert_eq!(total_samples(&paths[2]), samples);
+    // The frame straddling track 3's start went to track 2:
+    let first_frame_ts = (4 * SAMPLE_RATE).div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    assert_eq!(samples, 6 * SAMPLE_RATE - first_frame_ts);
+}
+
+#[test]
+fn frame_ownership_does_not_depend_on_the_plan() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(6, 3).write(dir.path().join("image.flac"));
+    let all = SplitOptions::new(dir.path().join("all"))
+        .split(&image)
+        .unwrap();
+
+    let mut plan = SplitOptions::new(dir.path().join("some"))
+        .plan(&image)
+        .unwrap();
+    plan.tracks_mut().remove(1);
+    let some = plan.execute().unwrap();
+    assert_eq!(frame_bodies(&some[0]), frame_bodies(&all[0]));
+    assert_eq!(frame_bodies(&some[1]), frame_bodies(&all[2]));
+}
+
+#[test]
+fn reports_warnings_and_the_tags_written() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(4, 2)
+        .tags(&[
+            "ARTIST=Someone",
+            "ALBUM=Something",
+            &format!("TITLE[1]={}", "x".repeat(100)),
+        ])
+        .write(dir.path().join("image.flac"));
+    let limits = PathLimits {
+        max_component_bytes: 40,
+        max_path_bytes: None,
+    };
+
+    let plan = SplitOptions::new(dir.path().join("out"))
+        .path_limits(limits)
+        .provenance(true)
+        .plan(&image)
+        .unwrap();
+    let mut report = plan.report();
+    assert!(
+        report.tracks[0]
+            .warnings
+            .iter()
+            .any(|warning| warning.starts_with("truncated the title")),
+        "{:?}",
+        report.tracks[0].warnings
+    );
+    assert!(
+        !report.tracks[0]
+            .tags
+            .iter()
+            .any(|tag| tag.key == "SOURCE_CUE_TRACK")
+    );
+
+    let paths = plan.execute_with_report(&mut report).unwrap();
+    let cue_track = TagEntry {
+        key: "SOURCE_CUE_TRACK".to_string(),
+        value: "2".to_string(),
+    };
+    assert!(report.tracks[1].tags.contains(&cue_track));
+    assert_eq!(tag_values(&paths[1], "SOURCE_CUE_TRACK"), ["2"]);
+}
+
+#[test]
+fn cues_past_the_end_of_the_stream() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut image = Image::new(6, 3);
+    image.tracks[2].1 = image.samples + 588;
+    let image = image.write(dir.path().join("image.flac"));
+    let out = dir.path().join("out");
+
+    let error = SplitOptions::new(&out).plan(&image).unwrap_err();
+    assert!(
+        matches!(error, Error::CueOutOfRange { track: 3, total_samples, .. }
+            if total_samples == 6 * SAMPLE_RATE),
+        "{error:?}"
+    );
+    assert!(!out.exists());
+}
+
+#[test]
+fn sidecar_track_tags_whatever_the_conventions() {
+    let dir = tempfile::tempdir().unwrap();
+    let image = Image::new(4, 2)
+        .tags(&[
+            "ARTIST=Artist",
+            "TITLE[1]=Not recognised",
+            "CUE_TRACK02_TITLE=Image",
+        ])
+        .write(dir.path().join("image.flac"));
+    fs::write(
+        dir.path().join("image.tags.csv"),
+        "TRACK,TITLE\n1,One\n2,Two\n",
+    )
+    .unwrap();
+    let options = SplitOptions::new(dir.path().join("out"))
+        .track_tag_conventions([TrackTagConvention::CueTrack]);
+    let titles = |options: &SplitOptions| -> Vec<Vec<String>> {
+        let plan = options.plan(&image).unwrap();
+        plan.tracks()
+            .iter()
+            .map(|planned| {
+                planned
+                    .track
+                    .tags
+                    .iter()
+                    .filter(|tag| tag.key == "TITLE")
+                    .map(|tag| tag.value.to_string())
+                    .collect()
+            })
+            .collect()
+    };
+
+    assert_eq!(titles(&options), [vec![], vec!["Image".to_string()]]);
+    assert_eq!(
+        titles(&options.clone().sidecar_tags(SidecarTags::Overlay)),
+        [["One"], ["Two"]]
+    );
+    assert_eq!(
+        titles(&options.sidecar_tags(SidecarTags::Replace)),
+        [["One"], ["Two"]]
+    );
+}